@@ -12,7 +12,7 @@ mod vm;
 fn main() {
     link_mod();
     run(
-        Mode::THREAD,
+        Mode::Thread,
         |wasm_uri| -> WasmCaller {
             custom_load_wasm(wasm_uri, Some(|module: &Module| -> Result<()>{
                 for x in module.exports().functions() {
@@ -27,7 +27,7 @@ fn main() {
                 let mut imports = wasi.import_object(store, module)?;
                 imports.register_namespace("env", import_namespace!({
                     "custom_a" => Function::new_typed_with_env(store, env, |env: FunctionEnvMut, a: i32| {
-                        #[cfg(debug_assertions)] println!("[VM:{:?}]custom_a: wasm_uri={}, a={}", env.data().thread_id(), env.data().wasm_uri(), a);
+                        #[cfg(debug_assertions)] println!("[VM:{}]custom_a: wasm_uri={}, a={}", env.data().id(), env.data().wasm_uri(), a);
                     }),
                 }));
                 Ok((wasi, imports))
@@ -44,8 +44,7 @@ fn main() {
             let rets = wasm_caller.raw_call(
                 "opposite_sign",
                 |ctx| {
-                    ctx.set_value_ptr(&ctx_value);
-                    ctx.value_bytes = ctx_value.write_to_bytes().unwrap();
+                    ctx.set_ctx_value(ctx_value.clone());
                     println!("set ctx: {:?}", ctx);
                     Ok(vec![(index as i32).into()].into_boxed_slice())
                 },
@@ -3,24 +3,23 @@ use wasmy_vm::*;
 
 use crate::{
     test::{TestArgs, TestCtxValue, TestRets},
-    vm::{run, Mode},
+    vm::run_async,
 };
 
 mod vm;
 
 fn main() {
     link_mod();
-    run(
-        Mode::TOKIO,
+    run_async(
         |wasm_uri| -> WasmCaller { load_wasm(wasm_uri).unwrap() },
-        |index: usize, wasm_caller: WasmCaller| {
+        |index: usize, wasm_caller: WasmCaller| async move {
             let mut ctx = TestCtxValue::new();
             ctx.set_value(env!("CARGO_PKG_VERSION").to_string());
             let mut data = TestArgs::new();
             data.set_a(2);
             data.set_b(5);
-            let rets: TestRets = wasm_caller.ctx_call(ctx, 0, data.clone()).unwrap();
-            // let rets: TestRets = wasm_caller.call(0, data.clone()).unwrap();
+            let rets: TestRets = wasm_caller.ctx_call_async(ctx, 0, data.clone()).await.unwrap();
+            // let rets: TestRets = wasm_caller.call_async(0, data.clone()).await.unwrap();
             println!("NO.{}: {}+{}={}", index, data.get_a(), data.get_b(), rets.get_c());
         },
     )
@@ -50,12 +49,13 @@ fn link_mod() {
 //     Ok(rets)
 // }
 //
-// #[allow(redundant_semicolons)]
+// #[allow(redundant_semicolons, unused_variables)]
 // fn
-// _wasmy_vm_handle_0(ctx_ptr: usize, args: &::wasmy_vm::Any) -> ::wasmy_vm::
-// Result<::wasmy_vm::Any>
+// _wasmy_vm_handle_0(env: &mut ::wasmy_vm::HostEnv, args: &::wasmy_vm::Any) ->
+// ::wasmy_vm::Result<::wasmy_vm::Any>
 // {
-//     add(unsafe { ::wasmy_vm::VmHandlerApi::try_as(ctx_ptr) }, ::wasmy_vm
+//     let ctx = env.ctx;
+//     add(::wasmy_vm::VmHandlerApi::try_as(ctx)?, ::wasmy_vm
 //     ::VmHandlerApi::unpack_any(args)
 //         ?).and_then(|res| ::wasmy_vm::VmHandlerApi::pack_any(res))
 // } ::wasmy_vm::submit_handler! { :: wasmy_vm :: VmHandlerApi :: new(0i32,
@@ -12,7 +12,7 @@ mod vm;
 fn main() {
     link_mod();
     run(
-        Mode::THREAD,
+        Mode::Thread,
         |wasm_uri| -> WasmCaller { load_wasm(wasm_uri).unwrap() },
         |index: usize, wasm_caller: WasmCaller| {
             let mut data = TestArgs::new();
@@ -44,11 +44,12 @@ fn link_mod() {
 //     Ok(rets)
 // }
 //
-// #[allow(redundant_semicolons)]
+// #[allow(redundant_semicolons, unused_variables)]
 // fn
-// _wasmy_vm_handle_0(_ctx_ptr: usize, args: &::wasmy_vm::Any) -> ::wasmy_vm::
-// Result<::wasmy_vm::Any>
+// _wasmy_vm_handle_0(env: &mut ::wasmy_vm::HostEnv, args: &::wasmy_vm::Any) ->
+// ::wasmy_vm::Result<::wasmy_vm::Any>
 // {
+//     let ctx = env.ctx;
 //     add(::wasmy_vm::VmHandlerApi::unpack_any(args)
 //         ?).and_then(|res| ::wasmy_vm::VmHandlerApi::pack_any(res))
 // } ::wasmy_vm::submit_handler! { :: wasmy_vm :: VmHandlerApi :: new(0i32,
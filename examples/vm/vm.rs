@@ -1,4 +1,7 @@
-use std::{env, path::PathBuf, thread};
+// Each vm example uses a part of the shared runner.
+#![allow(dead_code)]
+
+use std::{env, future::Future, path::PathBuf, thread};
 
 use structopt::StructOpt;
 use wasmy_vm::*;
@@ -17,16 +20,16 @@ struct Opt {
     wasm_path: PathBuf,
 }
 
-#[allow(dead_code)]
 pub enum Mode {
-    TOKIO,
-    THREAD,
+    Tokio,
+    Thread,
 }
 
-pub fn run<L, C>(model: Mode, loader: L, callback: C)
+/// Parse the options, and load the wasm by the loader, returning its caller,
+/// the number of the threads and the number of executions per thread.
+fn load<L>(loader: L) -> (WasmCaller, usize, usize)
 where
     L: Fn(PathBuf) -> WasmCaller,
-    C: Fn(usize, WasmCaller) + Sync + Copy + Send + 'static,
 {
     println!("wasmy, easily customize my wasm app!");
     let mut opt: Opt = Opt::from_args();
@@ -40,33 +43,24 @@ where
 
     let caller = loader(wasm_path);
 
-    let thread_num = opt.thread_num.and_then(|c| Some(if c == 0 { 1 } else { c })).unwrap_or(1);
-    let number = opt.number.and_then(|c| Some(if c == 0 { 1 } else { c })).unwrap_or(1);
+    let thread_num = opt.thread_num.map_or(1, |c| c.max(1));
+    let number = opt.number.map_or(1, |c| c.max(1));
+    (caller, thread_num, number)
+}
 
+pub fn run<L, C>(model: Mode, loader: L, callback: C)
+where
+    L: Fn(PathBuf) -> WasmCaller,
+    C: Fn(usize, WasmCaller) + Sync + Copy + Send + 'static,
+{
+    let (caller, thread_num, number) = load(loader);
     match model {
-        Mode::TOKIO => {
-            tokio::runtime::Builder::new_multi_thread()
-                .worker_threads(thread_num)
-                .enable_all()
-                .build()
-                .unwrap()
-                .block_on(async {
-                    for i in 0..thread_num {
-                        let caller2 = caller.clone();
-                        tokio::spawn(async move {
-                            for j in 0..number {
-                                callback(i * number + j, caller2.clone())
-                            }
-                        });
-                    }
-                });
-        }
-        Mode::THREAD => {
+        Mode::Tokio => spawn_tokio(caller, thread_num, number, move |index, caller| async move {
+            callback(index, caller)
+        }),
+        Mode::Thread => {
             let mut hdls = vec![];
-            let number = opt.number.and_then(|c| Some(if c == 0 { 1 } else { c })).unwrap_or(1);
-
-            for i in 1..=opt.thread_num.and_then(|c| Some(if c == 0 { 1 } else { c })).unwrap_or(1)
-            {
+            for i in 0..thread_num {
                 let caller2 = caller.clone();
                 hdls.push(thread::spawn(move || {
                     for j in 0..number {
@@ -80,3 +74,41 @@ where
         }
     };
 }
+
+/// Run the callback on the tokio runtime, awaiting the async wasm calls
+/// directly.
+pub fn run_async<L, C, F>(loader: L, callback: C)
+where
+    L: Fn(PathBuf) -> WasmCaller,
+    C: Fn(usize, WasmCaller) -> F + Sync + Copy + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (caller, thread_num, number) = load(loader);
+    spawn_tokio(caller, thread_num, number, callback)
+}
+
+fn spawn_tokio<C, F>(caller: WasmCaller, thread_num: usize, number: usize, callback: C)
+where
+    C: Fn(usize, WasmCaller) -> F + Sync + Copy + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(thread_num)
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let mut hdls = vec![];
+            for i in 0..thread_num {
+                let caller2 = caller.clone();
+                hdls.push(tokio::spawn(async move {
+                    for j in 0..number {
+                        callback(i * number + j, caller2.clone()).await
+                    }
+                }));
+            }
+            for h in hdls {
+                let _ = h.await;
+            }
+        });
+}
@@ -45,7 +45,7 @@ fn multiply(ctx: WasmCtx, args: TestArgs) -> Result<TestRets> {
 // #[inline]
 // #[no_mangle]
 // pub extern "C" fn
-// _wasmy_wasm_handle_0(ctx_size: i32, args_size: i32)
+// _wasmy_wasm_handle_0(ctx_size: i32, offset: i32, args_size: i32)
 // {
 //     #[allow(unused_mut)]
 //     #[inline]
@@ -55,5 +55,5 @@ fn multiply(ctx: WasmCtx, args: TestArgs) -> Result<TestRets> {
 //     { ::wasmy_abi::pack_any(multiply(ctx, args.get_args()?)?) }
 //     ;
 //     ::
-//     wasmy_abi::wasm_handle(ctx_size, args_size, _inner)
+//     wasmy_abi::wasm_handle(ctx_size, offset, args_size, _inner)
 // }
@@ -6,7 +6,7 @@ fn main() {
     protoc_rust::Codegen::new()
         .out_dir("./src/")
         .include("./")
-        .inputs(&["./abi.proto", "./test.proto"])
+        .inputs(["./abi.proto", "./test.proto"])
         .customize(Customize {
             carllerche_bytes_for_bytes: Some(true),
             serde_derive: Some(true),
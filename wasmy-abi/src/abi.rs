@@ -29,6 +29,7 @@ pub struct InArgs {
     // message fields
     pub method: i32,
     pub data: ::protobuf::SingularPtrField<::protobuf::well_known_types::Any>,
+    pub payload: ::bytes::Bytes,
     // special fields
     #[cfg_attr(feature = "with-serde", serde(skip))]
     pub unknown_fields: ::protobuf::UnknownFields,
@@ -94,6 +95,32 @@ impl InArgs {
     pub fn take_data(&mut self) -> ::protobuf::well_known_types::Any {
         self.data.take().unwrap_or_else(|| ::protobuf::well_known_types::Any::new())
     }
+
+    // bytes payload = 3;
+
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+    pub fn clear_payload(&mut self) {
+        self.payload.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_payload(&mut self, v: ::bytes::Bytes) {
+        self.payload = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_payload(&mut self) -> &mut ::bytes::Bytes {
+        &mut self.payload
+    }
+
+    // Take field
+    pub fn take_payload(&mut self) -> ::bytes::Bytes {
+        ::std::mem::replace(&mut self.payload, ::bytes::Bytes::new())
+    }
 }
 
 impl ::protobuf::Message for InArgs {
@@ -120,6 +147,9 @@ impl ::protobuf::Message for InArgs {
                 2 => {
                     ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.data)?;
                 },
+                3 => {
+                    ::protobuf::rt::read_singular_proto3_carllerche_bytes_into(wire_type, is, &mut self.payload)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -139,6 +169,9 @@ impl ::protobuf::Message for InArgs {
             let len = v.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         }
+        if !self.payload.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(3, &self.payload);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -153,6 +186,9 @@ impl ::protobuf::Message for InArgs {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         }
+        if !self.payload.is_empty() {
+            os.write_bytes(3, &self.payload)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -201,6 +237,11 @@ impl ::protobuf::Message for InArgs {
                 |m: &InArgs| { &m.data },
                 |m: &mut InArgs| { &mut m.data },
             ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeCarllercheBytes>(
+                "payload",
+                |m: &InArgs| { &m.payload },
+                |m: &mut InArgs| { &mut m.payload },
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<InArgs>(
                 "InArgs",
                 fields,
@@ -219,6 +260,7 @@ impl ::protobuf::Clear for InArgs {
     fn clear(&mut self) {
         self.method = 0;
         self.data.clear();
+        self.payload.clear();
         self.unknown_fields.clear();
     }
 }
@@ -242,6 +284,10 @@ pub struct OutRets {
     pub code: i32,
     pub msg: ::std::string::String,
     pub data: ::protobuf::SingularPtrField<::protobuf::well_known_types::Any>,
+    pub details: ::protobuf::RepeatedField<::protobuf::well_known_types::Any>,
+    pub location: ::std::string::String,
+    pub cause: ::protobuf::SingularPtrField<OutRets>,
+    pub payload: ::bytes::Bytes,
     // special fields
     #[cfg_attr(feature = "with-serde", serde(skip))]
     pub unknown_fields: ::protobuf::UnknownFields,
@@ -333,6 +379,116 @@ impl OutRets {
     pub fn take_data(&mut self) -> ::protobuf::well_known_types::Any {
         self.data.take().unwrap_or_else(|| ::protobuf::well_known_types::Any::new())
     }
+
+    // repeated .google.protobuf.Any details = 4;
+
+
+    pub fn get_details(&self) -> &[::protobuf::well_known_types::Any] {
+        &self.details
+    }
+    pub fn clear_details(&mut self) {
+        self.details.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_details(&mut self, v: ::protobuf::RepeatedField<::protobuf::well_known_types::Any>) {
+        self.details = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_details(&mut self) -> &mut ::protobuf::RepeatedField<::protobuf::well_known_types::Any> {
+        &mut self.details
+    }
+
+    // Take field
+    pub fn take_details(&mut self) -> ::protobuf::RepeatedField<::protobuf::well_known_types::Any> {
+        ::std::mem::replace(&mut self.details, ::protobuf::RepeatedField::new())
+    }
+
+    // string location = 5;
+
+
+    pub fn get_location(&self) -> &str {
+        &self.location
+    }
+    pub fn clear_location(&mut self) {
+        self.location.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_location(&mut self, v: ::std::string::String) {
+        self.location = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_location(&mut self) -> &mut ::std::string::String {
+        &mut self.location
+    }
+
+    // Take field
+    pub fn take_location(&mut self) -> ::std::string::String {
+        ::std::mem::replace(&mut self.location, ::std::string::String::new())
+    }
+
+    // .abi.OutRets cause = 6;
+
+
+    pub fn get_cause(&self) -> &OutRets {
+        self.cause.as_ref().unwrap_or_else(|| <OutRets as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_cause(&mut self) {
+        self.cause.clear();
+    }
+
+    pub fn has_cause(&self) -> bool {
+        self.cause.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_cause(&mut self, v: OutRets) {
+        self.cause = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_cause(&mut self) -> &mut OutRets {
+        if self.cause.is_none() {
+            self.cause.set_default();
+        }
+        self.cause.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_cause(&mut self) -> OutRets {
+        self.cause.take().unwrap_or_else(|| OutRets::new())
+    }
+
+    // bytes payload = 7;
+
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+    pub fn clear_payload(&mut self) {
+        self.payload.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_payload(&mut self, v: ::bytes::Bytes) {
+        self.payload = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_payload(&mut self) -> &mut ::bytes::Bytes {
+        &mut self.payload
+    }
+
+    // Take field
+    pub fn take_payload(&mut self) -> ::bytes::Bytes {
+        ::std::mem::replace(&mut self.payload, ::bytes::Bytes::new())
+    }
 }
 
 impl ::protobuf::Message for OutRets {
@@ -342,6 +498,16 @@ impl ::protobuf::Message for OutRets {
                 return false;
             }
         };
+        for v in &self.details {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        for v in &self.cause {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -362,6 +528,18 @@ impl ::protobuf::Message for OutRets {
                 3 => {
                     ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.data)?;
                 },
+                4 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.details)?;
+                },
+                5 => {
+                    ::protobuf::rt::read_singular_proto3_string_into(wire_type, is, &mut self.location)?;
+                },
+                6 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.cause)?;
+                },
+                7 => {
+                    ::protobuf::rt::read_singular_proto3_carllerche_bytes_into(wire_type, is, &mut self.payload)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -384,6 +562,20 @@ impl ::protobuf::Message for OutRets {
             let len = v.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         }
+        for value in &self.details {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        if !self.location.is_empty() {
+            my_size += ::protobuf::rt::string_size(5, &self.location);
+        }
+        if let Some(ref v) = self.cause.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        if !self.payload.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(7, &self.payload);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -401,6 +593,22 @@ impl ::protobuf::Message for OutRets {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         }
+        for v in &self.details {
+            os.write_tag(4, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        if !self.location.is_empty() {
+            os.write_string(5, &self.location)?;
+        }
+        if let Some(ref v) = self.cause.as_ref() {
+            os.write_tag(6, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        if !self.payload.is_empty() {
+            os.write_bytes(7, &self.payload)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -454,6 +662,26 @@ impl ::protobuf::Message for OutRets {
                 |m: &OutRets| { &m.data },
                 |m: &mut OutRets| { &mut m.data },
             ));
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<::protobuf::well_known_types::Any>>(
+                "details",
+                |m: &OutRets| { &m.details },
+                |m: &mut OutRets| { &mut m.details },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "location",
+                |m: &OutRets| { &m.location },
+                |m: &mut OutRets| { &mut m.location },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<OutRets>>(
+                "cause",
+                |m: &OutRets| { &m.cause },
+                |m: &mut OutRets| { &mut m.cause },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeCarllercheBytes>(
+                "payload",
+                |m: &OutRets| { &m.payload },
+                |m: &mut OutRets| { &mut m.payload },
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<OutRets>(
                 "OutRets",
                 fields,
@@ -473,6 +701,10 @@ impl ::protobuf::Clear for OutRets {
         self.code = 0;
         self.msg.clear();
         self.data.clear();
+        self.details.clear();
+        self.location.clear();
+        self.cause.clear();
+        self.payload.clear();
         self.unknown_fields.clear();
     }
 }
@@ -609,12 +841,16 @@ impl ::protobuf::reflect::ProtobufValue for Empty {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\tabi.proto\x12\x03abi\x1a\x19google/protobuf/any.proto\"J\n\x06InArgs\
+    \n\tabi.proto\x12\x03abi\x1a\x19google/protobuf/any.proto\"d\n\x06InArgs\
     \x12\x16\n\x06method\x18\x01\x20\x01(\x05R\x06method\x12(\n\x04data\x18\
-    \x02\x20\x01(\x0b2\x14.google.protobuf.AnyR\x04data\"Y\n\x07OutRets\x12\
-    \x12\n\x04code\x18\x01\x20\x01(\x05R\x04code\x12\x10\n\x03msg\x18\x02\
-    \x20\x01(\tR\x03msg\x12(\n\x04data\x18\x03\x20\x01(\x0b2\x14.google.prot\
-    obuf.AnyR\x04data\"\x07\n\x05Emptyb\x06proto3\
+    \x02\x20\x01(\x0b2\x14.google.protobuf.AnyR\x04data\x12\x18\n\x07payload\
+    \x18\x03\x20\x01(\x0cR\x07payload\"\xe3\x01\n\x07OutRets\x12\x12\n\x04co\
+    de\x18\x01\x20\x01(\x05R\x04code\x12\x10\n\x03msg\x18\x02\x20\x01(\tR\
+    \x03msg\x12(\n\x04data\x18\x03\x20\x01(\x0b2\x14.google.protobuf.AnyR\
+    \x04data\x12.\n\x07details\x18\x04\x20\x03(\x0b2\x14.google.protobuf.Any\
+    R\x07details\x12\x1a\n\x08location\x18\x05\x20\x01(\tR\x08location\x12\"\
+    \n\x05cause\x18\x06\x20\x01(\x0b2\x0c.abi.OutRetsR\x05cause\x12\x18\n\
+    \x07payload\x18\x07\x20\x01(\x0cR\x07payload\"\x07\n\x05Emptyb\x06proto3\
 ";
 
 static file_descriptor_proto_lazy: ::protobuf::rt::LazyV2<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::rt::LazyV2::INIT;
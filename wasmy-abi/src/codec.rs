@@ -0,0 +1,172 @@
+use protobuf::well_known_types::Any;
+
+use crate::types::*;
+
+/// The serialization formats of the payloads crossing the boundary between
+/// the wasm and the vm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CodecKind {
+    /// The protobuf messages packed into `Any` with their type URLs, the default.
+    Protobuf = 0,
+    /// The bytes as they are.
+    Raw = 1,
+    /// JSON via serde, requires the `json` feature.
+    Json = 2,
+    /// MessagePack via serde, requires the `msgpack` feature.
+    MessagePack = 3,
+    /// Postcard via serde, requires the `postcard` feature.
+    Postcard = 4,
+}
+
+impl CodecKind {
+    pub const fn bit(self) -> i32 {
+        1 << self as i32
+    }
+    pub const fn name(self) -> &'static str {
+        match self {
+            CodecKind::Protobuf => "protobuf",
+            CodecKind::Raw => "raw",
+            CodecKind::Json => "json",
+            CodecKind::MessagePack => "msgpack",
+            CodecKind::Postcard => "postcard",
+        }
+    }
+    /// The bit set of the codecs that this build supports, which the wasm
+    /// exports to negotiate with the vm when it is loaded.
+    #[allow(unused_mut)]
+    pub const fn supported() -> i32 {
+        let mut codecs = CodecKind::Protobuf.bit() | CodecKind::Raw.bit();
+        #[cfg(feature = "json")]
+        {
+            codecs |= CodecKind::Json.bit();
+        }
+        #[cfg(feature = "msgpack")]
+        {
+            codecs |= CodecKind::MessagePack.bit();
+        }
+        #[cfg(feature = "postcard")]
+        {
+            codecs |= CodecKind::Postcard.bit();
+        }
+        codecs
+    }
+}
+
+/// The codec of the payloads of type `T`, which is chosen by
+/// `#[wasm_handle(1, codec = "json")]` or `#[vm_handle(1, codec = "json")]`.
+/// The bytes of a codec other than protobuf are held by an `Any` without a
+/// type URL, and framed as they are in the payload field of `InArgs` and
+/// `OutRets`.
+pub trait Codec<T> {
+    const KIND: CodecKind;
+    fn pack(value: T) -> Result<Any>;
+    fn unpack(data: &Any) -> Result<T>;
+}
+
+/// Pack the value with the codec.
+pub fn pack_with<C: Codec<T>, T>(value: T) -> Result<Any> {
+    C::pack(value)
+}
+
+/// Unpack the value with the codec.
+pub fn unpack_with<C: Codec<T>, T>(data: &Any) -> Result<T> {
+    C::unpack(data)
+}
+
+fn pack_bytes(value: Vec<u8>) -> Any {
+    let mut data = Any::new();
+    data.set_value(value);
+    data
+}
+
+fn unpack_bytes(kind: CodecKind, data: &Any) -> Result<&[u8]> {
+    if !data.get_type_url().is_empty() {
+        return CodeMsg::result(
+            CODE_PROTO,
+            format!(
+                "{}: the payload is the protobuf message {:?}",
+                kind.name(),
+                data.get_type_url()
+            ),
+        );
+    }
+    Ok(data.get_value())
+}
+
+/// The protobuf codec, the default, which packs the messages into `Any` with
+/// their type URLs.
+pub struct ProtobufCodec;
+
+impl<M: Message> Codec<M> for ProtobufCodec {
+    const KIND: CodecKind = CodecKind::Protobuf;
+    fn pack(value: M) -> Result<Any> {
+        pack_any(value)
+    }
+    fn unpack(data: &Any) -> Result<M> {
+        unpack_any(data)
+    }
+}
+
+/// The raw bytes codec, which does not encode at all.
+pub struct RawCodec;
+
+impl Codec<Vec<u8>> for RawCodec {
+    const KIND: CodecKind = CodecKind::Raw;
+    fn pack(value: Vec<u8>) -> Result<Any> {
+        Ok(pack_bytes(value))
+    }
+    fn unpack(data: &Any) -> Result<Vec<u8>> {
+        Ok(unpack_bytes(Self::KIND, data)?.to_vec())
+    }
+}
+
+/// The JSON codec of the serde types.
+#[cfg(feature = "json")]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for JsonCodec {
+    const KIND: CodecKind = CodecKind::Json;
+    fn pack(value: T) -> Result<Any> {
+        let value = serde_json::to_vec(&value).map_err(|e| CodeMsg::new(CODE_PROTO, e))?;
+        Ok(pack_bytes(value))
+    }
+    fn unpack(data: &Any) -> Result<T> {
+        serde_json::from_slice(unpack_bytes(CodecKind::Json, data)?)
+            .map_err(|e| CodeMsg::new(CODE_PROTO, e))
+    }
+}
+
+/// The MessagePack codec of the serde types.
+#[cfg(feature = "msgpack")]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for MsgPackCodec {
+    const KIND: CodecKind = CodecKind::MessagePack;
+    fn pack(value: T) -> Result<Any> {
+        let value = rmp_serde::to_vec(&value).map_err(|e| CodeMsg::new(CODE_PROTO, e))?;
+        Ok(pack_bytes(value))
+    }
+    fn unpack(data: &Any) -> Result<T> {
+        rmp_serde::from_slice(unpack_bytes(CodecKind::MessagePack, data)?)
+            .map_err(|e| CodeMsg::new(CODE_PROTO, e))
+    }
+}
+
+/// The postcard codec of the serde types.
+#[cfg(feature = "postcard")]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for PostcardCodec {
+    const KIND: CodecKind = CodecKind::Postcard;
+    fn pack(value: T) -> Result<Any> {
+        let value = postcard::to_allocvec(&value).map_err(|e| CodeMsg::new(CODE_PROTO, e))?;
+        Ok(pack_bytes(value))
+    }
+    fn unpack(data: &Any) -> Result<T> {
+        postcard::from_bytes(unpack_bytes(CodecKind::Postcard, data)?)
+            .map_err(|e| CodeMsg::new(CODE_PROTO, e))
+    }
+}
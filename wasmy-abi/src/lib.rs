@@ -1,11 +1,21 @@
 #![feature(try_trait_v2)]
 
 pub use abi::*;
+pub use codec::*;
+pub use stream::*;
 pub use types::*;
 pub use wasm::*;
-pub use wasmy_macros::{wasm_handle, wasm_onload};
+pub use wasmy_macros::{
+    wasm_handle, wasm_onload, wasm_raw_handle, wasm_requires, wasm_stream_handle,
+};
+pub use wasmy_mangle::{demangle_method_name, mangle_method_name};
 
+// The generated code predates some of the current lints.
+#[allow(renamed_and_removed_lints, unexpected_cfgs, unused_parens, mismatched_lifetime_syntaxes)]
 pub mod abi;
+mod codec;
+mod stream;
+#[allow(renamed_and_removed_lints, unexpected_cfgs, unused_parens, mismatched_lifetime_syntaxes)]
 pub mod test;
 pub mod types;
 mod wasm;
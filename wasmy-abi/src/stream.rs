@@ -0,0 +1,112 @@
+use std::io;
+
+use crate::{abi::*, types::*, wasm::*};
+
+// The stream functions of the virtual machine.
+extern "C" {
+    fn _wasmy_vm_stream_next() -> i64;
+    fn _wasmy_vm_stream_emit(offset: i32, size: i32) -> i32;
+}
+
+/// The underlying function of wasm to handle stream requests.
+pub fn wasm_stream_handle<F, W, Value>(ctx_size: i32, handle: F)
+where
+    F: Fn(W, StreamReader, StreamWriter) -> Result<()>,
+    W: WasmContext<Value>,
+    Value: Message,
+{
+    let reader = StreamReader { chunk: vec![], pos: 0, done: false };
+    let res: OutRets = match handle(W::from_size(ctx_size as usize), reader, StreamWriter(())) {
+        Ok(()) => OutRets::new(),
+        Err(err) => err.into(),
+    };
+    let buffer = res.write_to_bytes().unwrap();
+    unsafe { _wasmy_vm_restore(buffer.as_ptr() as i32, buffer.len() as i32) };
+}
+
+/// The input of a stream call, whose chunks are pulled from the vm one at a
+/// time, as an iterator of the chunks or as a reader of the bytes.
+pub struct StreamReader {
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl StreamReader {
+    /// Pull the next chunk from the vm, none at the end of the input.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        // the rest of the chunk that is partly read
+        if self.pos < self.chunk.len() {
+            let rest = self.chunk[self.pos..].to_vec();
+            self.pos = self.chunk.len();
+            return Ok(Some(rest));
+        }
+        if self.done {
+            return Ok(None);
+        }
+        let rets = RawRets::from_i64(unsafe { _wasmy_vm_stream_next() });
+        if rets.is_err {
+            return CodeMsg::result(CODE_MEM, "the virtual machine failed to allocate the chunk");
+        }
+        if rets.size == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        let size = rets.size as usize;
+        Ok(Some(unsafe { Vec::from_raw_parts(rets.offset as *mut u8, size, size) }))
+    }
+}
+
+impl Iterator for StreamReader {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+impl io::Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.chunk.len() {
+            match self.next_chunk() {
+                Ok(Some(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Ok(None) => return Ok(0),
+                Err(err) => return Err(io::Error::other(err.msg)),
+            }
+        }
+        let size = buf.len().min(self.chunk.len() - self.pos);
+        buf[..size].copy_from_slice(&self.chunk[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+/// The output of a stream call, whose chunks are handed to the vm as they are
+/// emitted.
+pub struct StreamWriter(());
+
+impl StreamWriter {
+    /// Emit the chunk to the vm, which fails once the vm refuses the output.
+    pub fn emit(&mut self, chunk: &[u8]) -> Result<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        match unsafe { _wasmy_vm_stream_emit(chunk.as_ptr() as i32, chunk.len() as i32) } {
+            0 => Ok(()),
+            code => CodeMsg::result(code, "the virtual machine refused the output"),
+        }
+    }
+}
+
+impl io::Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.emit(buf).map_err(|err| io::Error::other(err.msg))?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -530,11 +530,235 @@ impl ::protobuf::reflect::ProtobufValue for TestCtxValue {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+#[cfg_attr(feature = "with-serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct TestNested {
+    // special fields
+    #[cfg_attr(feature = "with-serde", serde(skip))]
+    pub unknown_fields: ::protobuf::UnknownFields,
+    #[cfg_attr(feature = "with-serde", serde(skip))]
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a TestNested {
+    fn default() -> &'a TestNested {
+        <TestNested as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl TestNested {
+    pub fn new() -> TestNested {
+        ::std::default::Default::default()
+    }
+}
+
+impl ::protobuf::Message for TestNested {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> TestNested {
+        TestNested::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let fields = ::std::vec::Vec::new();
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<TestNested>(
+                "TestNested",
+                fields,
+                file_descriptor_proto()
+            )
+        })
+    }
+
+    fn default_instance() -> &'static TestNested {
+        static instance: ::protobuf::rt::LazyV2<TestNested> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(TestNested::new)
+    }
+}
+
+impl ::protobuf::Clear for TestNested {
+    fn clear(&mut self) {
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for TestNested {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for TestNested {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
+    }
+}
+
+#[derive(Clone,PartialEq,Eq,Debug,Hash)]
+#[cfg_attr(feature = "with-serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum TestNested_Methods {
+    NONE = 0,
+    ADD = 2,
+}
+
+impl ::protobuf::ProtobufEnum for TestNested_Methods {
+    fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    fn from_i32(value: i32) -> ::std::option::Option<TestNested_Methods> {
+        match value {
+            0 => ::std::option::Option::Some(TestNested_Methods::NONE),
+            2 => ::std::option::Option::Some(TestNested_Methods::ADD),
+            _ => ::std::option::Option::None
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        static values: &'static [TestNested_Methods] = &[
+            TestNested_Methods::NONE,
+            TestNested_Methods::ADD,
+        ];
+        values
+    }
+
+    fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::EnumDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            ::protobuf::reflect::EnumDescriptor::new_pb_name::<TestNested_Methods>("TestNested.Methods", file_descriptor_proto())
+        })
+    }
+}
+
+impl ::std::marker::Copy for TestNested_Methods {
+}
+
+impl ::std::default::Default for TestNested_Methods {
+    fn default() -> Self {
+        TestNested_Methods::NONE
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for TestNested_Methods {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Enum(::protobuf::ProtobufEnum::descriptor(self))
+    }
+}
+
+#[derive(Clone,PartialEq,Eq,Debug,Hash)]
+#[cfg_attr(feature = "with-serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum TestMethods {
+    TEST_NONE = 0,
+    TEST_ADD = 1,
+}
+
+impl ::protobuf::ProtobufEnum for TestMethods {
+    fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    fn from_i32(value: i32) -> ::std::option::Option<TestMethods> {
+        match value {
+            0 => ::std::option::Option::Some(TestMethods::TEST_NONE),
+            1 => ::std::option::Option::Some(TestMethods::TEST_ADD),
+            _ => ::std::option::Option::None
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        static values: &'static [TestMethods] = &[
+            TestMethods::TEST_NONE,
+            TestMethods::TEST_ADD,
+        ];
+        values
+    }
+
+    fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::EnumDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            ::protobuf::reflect::EnumDescriptor::new_pb_name::<TestMethods>("TestMethods", file_descriptor_proto())
+        })
+    }
+}
+
+impl ::std::marker::Copy for TestMethods {
+}
+
+impl ::std::default::Default for TestMethods {
+    fn default() -> Self {
+        TestMethods::TEST_NONE
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for TestMethods {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Enum(::protobuf::ProtobufEnum::descriptor(self))
+    }
+}
+
 static file_descriptor_proto_data: &'static [u8] = b"\
     \n\ntest.proto\x12\x04test\"&\n\x08TestArgs\x12\x0c\n\x01a\x18\x01\x20\
     \x01(\x05R\x01a\x12\x0c\n\x01b\x18\x02\x20\x01(\x05R\x01b\"\x18\n\x08Tes\
     tRets\x12\x0c\n\x01c\x18\x01\x20\x01(\x05R\x01c\"$\n\x0cTestCtxValue\x12\
-    \x14\n\x05value\x18\x01\x20\x01(\tR\x05valueb\x06proto3\
+    \x14\n\x05value\x18\x01\x20\x01(\tR\x05value\"*\n\nTestNested\"\x1c\n\
+    \x07Methods\x12\x08\n\x04NONE\x10\0\x12\x07\n\x03ADD\x10\x02**\n\x0bTest\
+    Methods\x12\r\n\tTEST_NONE\x10\0\x12\x0c\n\x08TEST_ADD\x10\x01b\x06proto\
+    3\
 ";
 
 static file_descriptor_proto_lazy: ::protobuf::rt::LazyV2<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::rt::LazyV2::INIT;
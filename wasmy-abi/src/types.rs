@@ -1,8 +1,9 @@
 use std::{convert::Infallible, fmt::Formatter, marker::PhantomData, mem, ops::FromResidual};
 
+use bytes::Bytes;
 pub use protobuf::{well_known_types::Any, CodedOutputStream, Message, ProtobufEnum};
 
-use crate::abi::*;
+use crate::{abi::*, codec::*};
 
 pub type Method = i32;
 pub type WasmMethod = Method;
@@ -10,6 +11,106 @@ pub type VmMethod = Method;
 pub type CtxId = i32;
 pub type Result<T> = std::result::Result<T, CodeMsg>;
 
+/// The version of the ABI between the wasm and the vm, which is exported by
+/// every wasm built with wasmy-abi.
+pub const ABI_VERSION: AbiVersion = AbiVersion { major: 1, minor: 4 };
+
+/// The ABI version, whose compatibility follows the rule: the vm runs a wasm of
+/// the same major version and a minor version not greater than its own.
+///
+/// | wasm / vm | 1.0 | 1.1 | 1.2 | 1.3 | 1.4 | 2.0 |
+/// |-----------|-----|-----|-----|-----|-----|-----|
+/// | 1.0       | yes | yes | yes | yes | yes | no  |
+/// | 1.1       | no  | yes | yes | yes | yes | no  |
+/// | 1.2       | no  | no  | yes | yes | yes | no  |
+/// | 1.3       | no  | no  | no  | yes | yes | no  |
+/// | 1.4       | no  | no  | no  | no  | yes | no  |
+/// | 2.0       | no  | no  | no  | no  | no  | yes |
+///
+/// A wasm that does not export the version was built before it was, and
+/// speaks 1.0.
+///
+/// History:
+/// - 1.0: the protobuf payloads.
+/// - 1.1: the codecs negotiated by the exported `_wasmy_abi_codecs`, whose
+///   bytes are framed in the payload fields of the args and the rets.
+/// - 1.2: the raw bytes handlers and the exported `_wasmy_alloc` and
+///   `_wasmy_dealloc`.
+/// - 1.3: the args of the wasm methods and the rets of `_wasmy_vm_call` placed
+///   by the vm with `_wasmy_alloc`, instead of `_wasmy_vm_recall`.
+/// - 1.4: the stream handlers and the `_wasmy_vm_stream_next` and
+///   `_wasmy_vm_stream_emit` imports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AbiVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl AbiVersion {
+    /// The version of the wasm that does not export it.
+    pub const UNVERSIONED: AbiVersion = AbiVersion { major: 1, minor: 0 };
+
+    /// Encode the version as `major << 16 | minor`.
+    pub const fn to_i32(self) -> i32 {
+        ((self.major as u32) << 16 | self.minor as u32) as i32
+    }
+    pub const fn from_i32(version: i32) -> AbiVersion {
+        AbiVersion { major: (version as u32 >> 16) as u16, minor: version as u16 }
+    }
+    /// Whether the vm of this version runs the wasm of the version.
+    pub const fn supports(self, wasm: AbiVersion) -> bool {
+        self.major == wasm.major && self.minor >= wasm.minor
+    }
+}
+
+impl std::fmt::Display for AbiVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The rets of a raw bytes handler, the buffer allocated in the wasm that the
+/// vm frees with `_wasmy_dealloc` after reading it, passed as
+/// `offset << 32 | size` where the high bit of the size tells that the buffer
+/// holds the `OutRets` of an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawRets {
+    pub offset: u32,
+    pub size: u32,
+    pub is_err: bool,
+}
+
+impl RawRets {
+    const ERR_BIT: u32 = 1 << 31;
+
+    pub const fn to_i64(self) -> i64 {
+        let size = if self.is_err { self.size | Self::ERR_BIT } else { self.size };
+        ((self.offset as u64) << 32 | size as u64) as i64
+    }
+    pub const fn from_i64(rets: i64) -> RawRets {
+        let size = rets as u32;
+        RawRets {
+            offset: (rets as u64 >> 32) as u32,
+            size: size & !Self::ERR_BIT,
+            is_err: size & Self::ERR_BIT != 0,
+        }
+    }
+}
+
+/// The method id of a named method: the 32-bit FNV-1a hash of the name with
+/// the high bit set, so it never collides with the numeric methods.
+pub const fn method_id(name: &str) -> Method {
+    let bytes = name.as_bytes();
+    let mut hash: u32 = 0x811c9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x01000193);
+        i += 1;
+    }
+    (hash | 0x8000_0000) as Method
+}
+
 /// Default WasmContext implementation.
 #[derive(Debug, Clone)]
 pub struct WasmCtx<C: Message = Empty> {
@@ -17,15 +118,20 @@ pub struct WasmCtx<C: Message = Empty> {
     pub(crate) _priv: PhantomData<C>,
 }
 
+/// The error between the wasm and the vm, which keeps its details, location
+/// and cause across the boundary as the fields of `OutRets`.
 #[derive(Debug, Clone)]
 pub struct CodeMsg {
     pub code: i32,
     pub msg: String,
+    details: Vec<Any>,
+    location: Option<String>,
+    cause: Option<Box<CodeMsg>>,
 }
 
 impl CodeMsg {
     pub fn new<S: ToString>(code: i32, msg: S) -> CodeMsg {
-        CodeMsg { code, msg: msg.to_string() }
+        CodeMsg { code, msg: msg.to_string(), details: vec![], location: None, cause: None }
     }
     #[inline]
     pub fn result<T, S: ToString>(code: i32, msg: S) -> Result<T> {
@@ -34,6 +140,43 @@ impl CodeMsg {
     pub fn into_result<T>(self) -> Result<T> {
         Err(self)
     }
+    /// Add the typed detail, packed with `pack_any`.
+    pub fn with_detail(mut self, detail: Any) -> CodeMsg {
+        self.details.push(detail);
+        self
+    }
+    /// Set the error that causes this one.
+    pub fn with_cause(mut self, cause: CodeMsg) -> CodeMsg {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+    /// Set the location to the caller.
+    #[track_caller]
+    pub fn with_location(mut self) -> CodeMsg {
+        let caller = std::panic::Location::caller();
+        self.location = Some(format!("{}:{}:{}", caller.file(), caller.line(), caller.column()));
+        self
+    }
+    /// The typed details of the error, like the gRPC `Status`.
+    pub fn details(&self) -> &[Any] {
+        &self.details
+    }
+    /// The source location where the error is raised, as `file:line:column`.
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+    /// The error that causes this one.
+    pub fn cause(&self) -> Option<&CodeMsg> {
+        self.cause.as_deref()
+    }
+    /// Get the first detail of the message type, none if there is no such one.
+    pub fn get_detail<M: Message>(&self) -> Option<M> {
+        self.details.iter().find_map(|detail| detail.unpack::<M>().ok().flatten())
+    }
+    /// Iterate the chain of the errors, from this one to the root cause.
+    pub fn chain(&self) -> impl Iterator<Item = &CodeMsg> {
+        std::iter::successors(Some(self), |err| err.cause.as_deref())
+    }
 }
 
 pub type RetCode = i32;
@@ -47,10 +190,22 @@ pub const CODE_RUNTIME: RetCode = -6;
 pub const CODE_PROTO: RetCode = -7;
 pub const CODE_NONE: RetCode = -8;
 pub const CODE_MEM: RetCode = -9;
+pub const CODE_FUEL: RetCode = -10;
+pub const CODE_TIMEOUT: RetCode = -11;
+pub const CODE_CANCELED: RetCode = -12;
+pub const CODE_CONFLICT: RetCode = -13;
+pub const CODE_VERSION: RetCode = -14;
 
 impl std::fmt::Display for CodeMsg {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "code={}, msg={})", self.code, self.msg)
+        write!(f, "code={}, msg={})", self.code, self.msg)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        if let Some(cause) = &self.cause {
+            write!(f, ", caused by: {}", cause)?;
+        }
+        Ok(())
     }
 }
 
@@ -81,7 +236,7 @@ impl From<protobuf::ProtobufError> for CodeMsg {
 impl<R: Message> From<OutRets> for Result<R> {
     fn from(out_rets: OutRets) -> Self {
         if out_rets.get_code() != 0 {
-            return CodeMsg::result(out_rets.get_code(), out_rets.get_msg());
+            return Err(out_rets.into());
         }
         out_rets.get_data().unpack::<R>()?.map_or_else(
             || {
@@ -95,13 +250,47 @@ impl<R: Message> From<OutRets> for Result<R> {
     }
 }
 
+impl OutRets {
+    /// Convert into the result whose data is unpacked with the codec.
+    pub fn into_result_with<C: Codec<R>, R>(mut self) -> Result<R> {
+        if self.get_code() != 0 {
+            return Err(self.into());
+        }
+        C::unpack(&unframe(self.take_data(), self.take_payload()))
+    }
+}
+
 impl InArgs {
     pub fn try_new<M: Message>(method: Method, data: M) -> Result<InArgs> {
+        Self::try_new_with::<ProtobufCodec, M>(method, data)
+    }
+    /// Create the args whose data is packed with the codec.
+    pub fn try_new_with<C: Codec<T>, T>(method: Method, data: T) -> Result<InArgs> {
         let mut args = InArgs::new();
         args.set_method(method);
-        args.set_data(pack_any(data)?);
+        args.set_packed(C::pack(data)?);
         Ok(args)
     }
+    /// Get the args unpacked with the codec.
+    pub fn get_args_with<C: Codec<T>, T>(&self) -> Result<T> {
+        if self.payload.is_empty() {
+            C::unpack(self.get_data())
+        } else {
+            C::unpack(&unframe(Any::new(), self.payload.clone()))
+        }
+    }
+    /// Set the data packed by a codec, framing the bytes of a codec other than
+    /// protobuf in the payload.
+    pub fn set_packed(&mut self, data: Any) {
+        match frame(data) {
+            Ok(data) => self.set_data(data),
+            Err(payload) => self.set_payload(payload),
+        }
+    }
+    /// Take the data packed by a codec.
+    pub fn take_packed(&mut self) -> Any {
+        unframe(self.take_data(), self.take_payload())
+    }
     pub fn get_args<R: Message>(&self) -> Result<R> {
         self.get_data().unpack::<R>()?.map_or_else(
             || CodeMsg::result(CODE_PROTO, "protobuf: the message type does not match the in_args"),
@@ -134,34 +323,67 @@ impl From<CodeMsg> for OutRets {
         let mut res = OutRets::new();
         res.set_code(v.code);
         res.set_msg(v.msg);
+        res.set_details(v.details.into());
+        res.set_location(v.location.unwrap_or_default());
+        if let Some(cause) = v.cause {
+            res.set_cause((*cause).into());
+        }
         res
     }
 }
 
+/// Convert the error of the rets, whose data is dropped.
+impl From<OutRets> for CodeMsg {
+    fn from(mut v: OutRets) -> Self {
+        CodeMsg {
+            code: v.get_code(),
+            msg: v.take_msg(),
+            details: v.take_details().into_vec(),
+            location: Some(v.take_location()).filter(|location| !location.is_empty()),
+            cause: v.has_cause().then(|| Box::new(v.take_cause().into())),
+        }
+    }
+}
+
 impl From<Any> for OutRets {
     fn from(v: Any) -> Self {
         let mut res = OutRets::new();
-        res.set_data(v);
+        match frame(v) {
+            Ok(data) => res.set_data(data),
+            Err(payload) => res.set_payload(payload),
+        }
         res
     }
 }
 
+/// Split the data packed by a codec into the protobuf message, or the bytes of
+/// another codec, which have no type URL, to be framed as they are.
+fn frame(mut data: Any) -> std::result::Result<Any, Bytes> {
+    if data.get_type_url().is_empty() { Err(data.take_value().into()) } else { Ok(data) }
+}
+
+/// Join the data and the payload fields back into the data packed by a codec.
+fn unframe(data: Any, payload: Bytes) -> Any {
+    if payload.is_empty() {
+        return data;
+    }
+    let mut data = Any::new();
+    data.set_value(payload.to_vec());
+    data
+}
+
 impl<R: Message> From<Result<R>> for OutRets {
     fn from(v: Result<R>) -> Self {
         match v {
-            Ok(data) => {
-                let mut res = OutRets::new();
-                match pack_any(data) {
-                    Ok(data) => {
-                        res.set_data(data);
-                    }
-                    Err(err) => {
-                        res.set_code(CODE_PROTO);
-                        res.set_msg(err.to_string());
-                    }
+            Ok(data) => match pack_any(data) {
+                Ok(data) => data.into(),
+                Err(err) => {
+                    let mut res = OutRets::new();
+                    res.set_code(CODE_PROTO);
+                    res.set_msg(err.to_string());
+                    res
                 }
-                res
-            }
+            },
             Err(e) => e.into(),
         }
     }
@@ -187,7 +409,13 @@ impl FromResidual<Result<Infallible>> for OutRets {
 #[cfg(not(target_family = "wasm"))]
 impl From<wasmer::InstantiationError> for CodeMsg {
     fn from(v: wasmer::InstantiationError) -> Self {
-        CodeMsg::new(CODE_COMPILE, v)
+        match &v {
+            // failed to create the memories or tables within the limits
+            wasmer::InstantiationError::Link(wasmer::LinkError::Resource(_)) => {
+                CodeMsg::new(CODE_MEM, v)
+            }
+            _ => CodeMsg::new(CODE_COMPILE, v),
+        }
     }
 }
 
@@ -218,3 +446,43 @@ impl From<wasmer::RuntimeError> for CodeMsg {
         CodeMsg::new(CODE_RUNTIME, format!("{:?}", v))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_any, AbiVersion, CodeMsg, Message, RawRets, Result, CODE_NONE, CODE_PROTO};
+    use crate::abi::{Empty, InArgs, OutRets};
+
+    #[test]
+    fn abi_version() {
+        let version = AbiVersion { major: 1, minor: 2 };
+        assert_eq!(AbiVersion::from_i32(version.to_i32()), version);
+        assert!(version.supports(AbiVersion { major: 1, minor: 0 }));
+        assert!(!version.supports(AbiVersion { major: 1, minor: 3 }));
+        assert!(!version.supports(AbiVersion { major: 2, minor: 0 }));
+    }
+
+    #[test]
+    fn raw_rets() {
+        let rets = RawRets { offset: 0xfff0_0000, size: 12, is_err: true };
+        assert_eq!(RawRets::from_i64(rets.to_i64()), rets);
+    }
+
+    #[test]
+    fn code_msg_details() {
+        let mut detail = InArgs::new();
+        detail.set_method(7);
+        let err = CodeMsg::new(CODE_PROTO, "bad args")
+            .with_detail(pack_any(detail).unwrap())
+            .with_location()
+            .with_cause(CodeMsg::new(CODE_NONE, "not found"));
+        let bytes = OutRets::from(err).write_to_bytes().unwrap();
+        let err = Result::<Empty>::from(OutRets::parse_from_bytes(&bytes).unwrap()).unwrap_err();
+        assert_eq!((err.code, err.msg.as_str()), (CODE_PROTO, "bad args"));
+        assert_eq!(err.get_detail::<InArgs>().unwrap().get_method(), 7);
+        assert!(err.get_detail::<Empty>().is_none());
+        assert!(err.location().unwrap().starts_with(file!()));
+        let codes: Vec<_> = err.chain().map(|err| err.code).collect();
+        assert_eq!(codes, vec![CODE_PROTO, CODE_NONE]);
+        assert!(err.cause().unwrap().location().is_none());
+    }
+}
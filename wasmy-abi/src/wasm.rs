@@ -1,21 +1,84 @@
-use std::marker::PhantomData;
+use std::{cell::RefCell, marker::PhantomData};
 
+pub use inventory::submit as submit_types;
 pub use protobuf::{well_known_types::Any, CodedOutputStream, Message, ProtobufEnum};
 
-use crate::{abi::*, types::*};
+use crate::{abi::*, codec::*, types::*};
 
 // The ABI interaction functions of the virtual machine.
 extern "C" {
     pub(crate) fn _wasmy_vm_recall(is_ctx: i32, offset: i32);
     pub(crate) fn _wasmy_vm_restore(offset: i32, size: i32);
-    pub(crate) fn _wasmy_vm_invoke(offset: i32, size: i32) -> i32;
+    pub(crate) fn _wasmy_vm_call(offset: i32, size: i32) -> i64;
+}
+
+/// Export the ABI version that the wasm is built with, checked by the vm when
+/// the wasm is loaded.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub extern "C" fn _wasmy_abi_version() -> i32 {
+    ABI_VERSION.to_i32()
+}
+
+/// Export the codecs that the wasm supports, negotiated with the vm when the
+/// wasm is loaded.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub extern "C" fn _wasmy_abi_codecs() -> i32 {
+    CodecKind::supported()
+}
+
+/// Allocate the buffer of the size for the vm to write into, which is owned by
+/// the function that it is then passed to.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub extern "C" fn _wasmy_alloc(size: i32) -> i32 {
+    let mut buffer = std::mem::ManuallyDrop::new(Vec::<u8>::with_capacity(size as usize));
+    buffer.as_mut_ptr() as i32
+}
+
+/// Free the buffer returned to the vm, which is kept with its capacity.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub extern "C" fn _wasmy_dealloc(offset: i32, _size: i32) {
+    free_returned(offset as u32);
+}
+
+thread_local! {
+    /// The buffers left in the linear memory for the vm to read, which are
+    /// kept as they are until the vm frees them, so they are neither shrunk
+    /// nor freed with another capacity.
+    static RETURNED: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Leave the buffer in the linear memory for the vm to read and free,
+/// returning its offset.
+fn return_buffer(buffer: Vec<u8>) -> u32 {
+    let offset = buffer.as_ptr() as u32;
+    RETURNED.with(|returned| returned.borrow_mut().push(buffer));
+    offset
+}
+
+/// Free the buffer left for the vm, false if there is none at the offset.
+#[cfg(any(target_family = "wasm", test))]
+fn free_returned(offset: u32) -> bool {
+    RETURNED.with(|returned| {
+        let mut returned = returned.borrow_mut();
+        match returned.iter().position(|buffer| buffer.as_ptr() as u32 == offset) {
+            Some(index) => {
+                returned.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    })
 }
 
-const NON_CTX: i32 = 0;
 const IS_CTX: i32 = 1;
 
-/// The underlying function of wasm to handle requests.
-pub fn wasm_handle<F, W, Value>(ctx_size: i32, args_size: i32, handle: F)
+/// The underlying function of wasm to handle requests, which takes the args
+/// buffer written by the vm into `_wasmy_alloc` memory.
+pub fn wasm_handle<F, W, Value>(ctx_size: i32, offset: i32, args_size: i32, handle: F)
 where
     F: Fn(W, InArgs) -> Result<Any>,
     W: WasmContext<Value>,
@@ -24,8 +87,8 @@ where
     if args_size <= 0 {
         return;
     }
-    let mut buffer = vec![0u8; args_size as usize];
-    unsafe { _wasmy_vm_recall(NON_CTX, buffer.as_ptr() as i32) };
+    let size = args_size as usize;
+    let mut buffer = unsafe { Vec::from_raw_parts(offset as *mut u8, size, size) };
     let res: OutRets = match InArgs::parse_from_bytes(&buffer) {
         Ok(args) => handle(W::from_size(ctx_size as usize), args).into(),
         Err(err) => CodeMsg::new(CODE_PROTO, err).into(),
@@ -42,6 +105,78 @@ where
     unsafe { _wasmy_vm_restore(buffer.as_ptr() as i32, buffer.len() as i32) };
 }
 
+/// The underlying function of wasm to handle raw bytes requests, which takes
+/// the args buffer written by the vm into `_wasmy_alloc` memory, and leaves
+/// the rets buffer in the linear memory for the vm to read and free.
+pub fn wasm_raw_handle<F, W, Value>(offset: i32, size: i32, handle: F) -> i64
+where
+    F: Fn(W, Vec<u8>) -> Result<Vec<u8>>,
+    W: WasmContext<Value>,
+    Value: Message,
+{
+    let args = unsafe { Vec::from_raw_parts(offset as *mut u8, size as usize, size as usize) };
+    let (rets, is_err) = match handle(W::from_size(0), args) {
+        Ok(rets) => (rets, false),
+        Err(err) => (OutRets::from(err).write_to_bytes().unwrap(), true),
+    };
+    let size = rets.len() as u32;
+    RawRets { offset: return_buffer(rets), size, is_err }.to_i64()
+}
+
+/// The message types of a method, which `#[wasm_handle]` collects for
+/// `_wasmy_manifest`.
+#[cfg_attr(not(target_family = "wasm"), allow(dead_code))]
+pub struct WasmHandlerTypes {
+    symbol: &'static str,
+    args: Option<fn() -> &'static str>,
+    rets: Option<fn() -> &'static str>,
+}
+
+inventory::collect!(WasmHandlerTypes);
+
+impl WasmHandlerTypes {
+    /// The message types of the method exported by the symbol, where an
+    /// unknown one is none.
+    pub const fn new(
+        symbol: &'static str,
+        args: Option<fn() -> &'static str>,
+        rets: Option<fn() -> &'static str>,
+    ) -> Self {
+        WasmHandlerTypes { symbol, args, rets }
+    }
+}
+
+/// The full name of the protobuf message, e.g. `abi.Empty`.
+pub fn message_full_name<M: Message>() -> &'static str {
+    M::descriptor_static().full_name()
+}
+
+/// Export the message types of the methods, read by the vm when the wasm is
+/// loaded, and left in the linear memory for the vm to read and free.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub extern "C" fn _wasmy_manifest() -> i64 {
+    let manifest = manifest_bytes();
+    let size = manifest.len() as u32;
+    RawRets { offset: return_buffer(manifest), size, is_err: false }.to_i64()
+}
+
+/// One record of the exported symbol, the full name of the args and the one of
+/// the rets per method, each a string of the u16 length in little endian, where
+/// an unknown type is empty.
+#[cfg(any(target_family = "wasm", test))]
+fn manifest_bytes() -> Vec<u8> {
+    let mut manifest = vec![];
+    for types in inventory::iter::<WasmHandlerTypes> {
+        let names = [types.symbol, types.args.map_or("", |f| f()), types.rets.map_or("", |f| f())];
+        for name in names {
+            manifest.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            manifest.extend_from_slice(name.as_bytes());
+        }
+    }
+    manifest
+}
+
 /// WasmContext is wasm context abstraction.
 pub trait WasmContext<Value: Message = Empty> {
     fn from_size(size: usize) -> Self;
@@ -60,15 +195,30 @@ pub trait WasmContext<Value: Message = Empty> {
         }
     }
     fn call_vm<M: Message, R: Message>(&self, method: VmMethod, data: M) -> Result<R> {
-        let args = InArgs::try_new(method, data)?;
-        let mut buffer = args.write_to_bytes().unwrap();
-        let size = unsafe { _wasmy_vm_invoke(buffer.as_ptr() as i32, buffer.len() as i32) };
-        if size <= 0 {
-            return Ok(R::new());
+        match invoke_vm(InArgs::try_new(method, data)?)? {
+            Some(rets) => rets.into(),
+            None => Ok(R::new()),
         }
-        buffer.resize(size as usize, 0);
-        unsafe { _wasmy_vm_recall(NON_CTX, buffer.as_ptr() as i32) };
-        OutRets::parse_from_bytes(buffer.as_slice())?.into()
+    }
+    /// Call the vm handler registered with the codec, e.g.
+    /// `#[vm_handle(1, codec = "json")]`.
+    fn call_vm_with<C: Codec<M> + Codec<R>, M, R>(&self, method: VmMethod, data: M) -> Result<R> {
+        match invoke_vm(InArgs::try_new_with::<C, M>(method, data)?)? {
+            Some(rets) => rets.into_result_with::<C, R>(),
+            None => CodeMsg::result(CODE_NONE, "the virtual machine returns nothing"),
+        }
+    }
+    /// Call the vm handler registered by `#[vm_handle(name = "...")]`.
+    fn call_vm_named<M: Message, R: Message>(&self, name: &str, data: M) -> Result<R> {
+        self.call_vm(method_id(name), data)
+    }
+    /// Call the vm handler registered by `#[vm_handle(Enum::VARIANT)]`.
+    fn call_vm_enum<E: ProtobufEnum, M: Message, R: Message>(
+        &self,
+        method: E,
+        data: M,
+    ) -> Result<R> {
+        self.call_vm(method.value(), data)
     }
 }
 
@@ -80,3 +230,55 @@ impl<Value: Message> WasmContext<Value> for WasmCtx<Value> {
         self.size
     }
 }
+
+/// Invoke the vm with the args, none if it returns nothing. The vm writes the
+/// rets into `_wasmy_alloc` memory, which is owned here.
+fn invoke_vm(args: InArgs) -> Result<Option<OutRets>> {
+    let buffer = args.write_to_bytes().unwrap();
+    let rets = unsafe { _wasmy_vm_call(buffer.as_ptr() as i32, buffer.len() as i32) };
+    let rets = RawRets::from_i64(rets);
+    if rets.is_err {
+        return CodeMsg::result(CODE_MEM, "the virtual machine failed to allocate the rets");
+    }
+    if rets.size == 0 {
+        return Ok(None);
+    }
+    let size = rets.size as usize;
+    let buffer = unsafe { Vec::from_raw_parts(rets.offset as *mut u8, size, size) };
+    Ok(Some(OutRets::parse_from_bytes(buffer.as_slice())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        free_returned, manifest_bytes, message_full_name, return_buffer, WasmHandlerTypes,
+    };
+    use crate::{Empty, InArgs};
+
+    inventory::submit! {
+        WasmHandlerTypes::new("_wasmy_wasm_handle_1", Some(message_full_name::<Empty>), None)
+    }
+
+    #[test]
+    fn returned_buffer() {
+        let mut buffer = Vec::with_capacity(16);
+        buffer.extend_from_slice(b"rets");
+        let ptr = buffer.as_ptr();
+        // the buffer is not shrunk to its size, which would move it
+        let offset = return_buffer(buffer);
+        assert_eq!(offset, ptr as u32);
+        assert!(free_returned(offset));
+        assert!(!free_returned(offset));
+    }
+
+    #[test]
+    fn manifest() {
+        let mut expected = vec![20, 0];
+        expected.extend_from_slice(b"_wasmy_wasm_handle_1");
+        expected.extend_from_slice(&[9, 0]);
+        expected.extend_from_slice(b"abi.Empty");
+        expected.extend_from_slice(&[0, 0]);
+        assert_eq!(manifest_bytes(), expected);
+        assert_eq!(message_full_name::<InArgs>(), "abi.InArgs");
+    }
+}
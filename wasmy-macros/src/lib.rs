@@ -1,9 +1,12 @@
+#![cfg_attr(test, allow(dead_code))] // the macros are compiled out of the tests, see rust-lang/rust#62127
+
 use std::ops::Deref;
 
 use proc_macro::TokenStream;
 use proc_macro2::{Ident, Span};
 use quote::quote;
-use syn::{parse::Parser, FnArg, ItemFn, Lit, Pat, Signature};
+use syn::{parse::Parser, FnArg, ItemFn, Lit, Pat, Signature, Type};
+use wasmy_mangle::mangle_method_name;
 
 // syn::AttributeArgs does not implement syn::Parse
 type AttributeArgs = syn::punctuated::Punctuated<syn::NestedMeta, syn::Token![,]>;
@@ -15,48 +18,103 @@ type AttributeArgs = syn::punctuated::Punctuated<syn::NestedMeta, syn::Token![,]
 /// #[vm_handle(123)]
 /// fn xxx<A: wasmy_abi::Message, R: wasmy_abi::Message>(args: A) -> wasmy_abi::Result<R> {todo!()}
 /// ```
-/// or with context
+/// or with the context of `ctx_call`, which fails with `CODE_PROTO` if the
+/// context is of another type
 /// ```
 /// #[vm_handle(123)]
 /// fn yyy<C: wasmy_abi::Message, A: wasmy_abi::Message, R: wasmy_abi::Message>(ctx: Option<&C>, args: A) -> wasmy_abi::Result<R> {todo!()}
 /// ```
+/// or with the host state of the call or the instance marked by `#[state]`,
+/// which fails with `CODE_NONE` if there is no state of the type
+/// ```
+/// #[vm_handle(123)]
+/// fn www<S: 'static, A: wasmy_abi::Message, R: wasmy_abi::Message>(#[state] state: &mut S, args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or in a handler set, which is collected by `HandlerSet::collect("plugin")`
+/// and bound to the wasm modules loaded with it, instead of the global handlers
+/// ```
+/// #[vm_handle(123, set = "plugin")]
+/// fn vvv<A: wasmy_abi::Message, R: wasmy_abi::Message>(args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or named, which is called by `WasmContext::call_vm_named("kv.get", ..)`
+/// ```
+/// #[vm_handle(name = "kv.get")]
+/// fn uuu<A: wasmy_abi::Message, R: wasmy_abi::Message>(args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or with a protobuf enum variant (or an `i32` constant) as the method,
+/// which is called by `WasmContext::call_vm_enum(MyMethods::Get, ..)`
+/// ```
+/// #[vm_handle(MyMethods::Get)]
+/// fn ttt<A: wasmy_abi::Message, R: wasmy_abi::Message>(args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or with a codec other than protobuf: `raw`, `json`, `msgpack`, `postcard`,
+/// or the path of a custom `wasmy_abi::Codec`, which is called by
+/// `WasmContext::call_vm_with::<JsonCodec, _, _>(1, ..)`
+/// ```
+/// #[vm_handle(123, codec = "json")]
+/// fn sss<A: serde::de::DeserializeOwned, R: serde::Serialize>(args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or async (requires the `async` feature of wasmy-vm), which blocks on the
+/// tokio runtime of the caller, so the wasm is called in a multi-thread
+/// runtime or by `WasmCaller::call_async`, or it fails with `CODE_RUNTIME`
+/// ```
+/// #[vm_handle(123)]
+/// async fn zzz<A: wasmy_abi::Message, R: wasmy_abi::Message>(args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
 /// command to check expanded code: `cargo +nightly rustc -- -Zunstable-options
 /// --pretty=expanded`
 #[proc_macro_attribute]
 #[cfg(not(test))] // Work around for rust-lang/rust#62127
 pub fn vm_handle(args: TokenStream, item: TokenStream) -> TokenStream {
-    let method = parse_method("vm_handle", args).unwrap();
-    let raw_item = proc_macro2::TokenStream::from(item.clone());
-    let raw_sig = syn::parse_macro_input!(item as ItemFn).sig;
-    let has_ctx = raw_sig.inputs.len() == 2;
-    let raw_ident = raw_sig.ident;
-    let new_ident = Ident::new(&format!("_wasmy_vm_handle_{}", method), Span::call_site());
-
-    let new_item = if has_ctx {
-        quote! {
-            #raw_item
-
-
-            #[allow(redundant_semicolons)]
-            fn #new_ident(ctx_ptr: usize, args: &::wasmy_vm::Any) -> ::wasmy_vm::Result<::wasmy_vm::Any> {
-                #raw_ident(unsafe{::wasmy_vm::VmHandlerApi::try_as(ctx_ptr)}, ::wasmy_vm::VmHandlerApi::unpack_any(args)?).and_then(|res|::wasmy_vm::VmHandlerApi::pack_any(res))
-            }
-            ::wasmy_vm::submit_handler!{
-               ::wasmy_vm::VmHandlerApi::new(#method, #new_ident)
-            }
+    let attr = parse_method_attr("vm_handle", args, true).unwrap();
+    let codec = match attr.codec(quote! {::wasmy_vm}) {
+        Ok(codec) => codec,
+        Err(e) => return TokenStream::from(e.to_compile_error()),
+    };
+    let HandleAttr { method, set, .. } = attr;
+    let mut raw_item = syn::parse_macro_input!(item as ItemFn);
+    let call_args = match vm_call_args(&mut raw_item.sig, &codec) {
+        Ok(call_args) => call_args,
+        Err(e) => return TokenStream::from(e.to_compile_error()),
+    };
+    let is_async = raw_item.sig.asyncness.is_some();
+    let raw_ident = raw_item.sig.ident.clone();
+    let (new_ident, register) = match &set {
+        Some(set) => {
+            let name: String =
+                set.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+            (
+                Ident::new(&method.symbol("_wasmy_vm", &format!("_{}", name)), Span::call_site()),
+                quote! {.in_set(#set)},
+            )
+        }
+        None => (Ident::new(&method.symbol("_wasmy_vm", ""), Span::call_site()), quote! {}),
+    };
+    let (method, method_name) = match &method {
+        MethodKey::Id(id) => (quote! {#id}, quote! {}),
+        MethodKey::Name(name) => {
+            (quote! {::wasmy_vm::method_id(#name)}, quote! {.with_method_name(#name)})
         }
+        MethodKey::Enum(path, _) => (quote! {#path as ::wasmy_vm::Method}, quote! {}),
+    };
+    let call_raw = if is_async {
+        quote! {::wasmy_vm::VmHandlerApi::block_on(#raw_ident(#(#call_args),*))}
     } else {
-        quote! {
-            #raw_item
+        quote! {#raw_ident(#(#call_args),*)}
+    };
 
+    let new_item = quote! {
+        #raw_item
 
-            #[allow(redundant_semicolons)]
-            fn #new_ident(_ctx_ptr: usize, args: &::wasmy_vm::Any) -> ::wasmy_vm::Result<::wasmy_vm::Any> {
-                #raw_ident(::wasmy_vm::VmHandlerApi::unpack_any(args)?).and_then(|res|::wasmy_vm::VmHandlerApi::pack_any(res))
-            }
-            ::wasmy_vm::submit_handler!{
-               ::wasmy_vm::VmHandlerApi::new(#method, #new_ident)
-            }
+
+        #[allow(redundant_semicolons, unused_variables)]
+        fn #new_ident(env: &mut ::wasmy_vm::HostEnv, args: &::wasmy_vm::Any) -> ::wasmy_vm::Result<::wasmy_vm::Any> {
+            let ctx = env.ctx;
+            #call_raw.and_then(|res|::wasmy_vm::pack_with::<#codec, _>(res))
+        }
+        ::wasmy_vm::submit_handler!{
+           ::wasmy_vm::VmHandlerApi::new(#method, #new_ident)
+               .with_name(concat!(module_path!(), "::", stringify!(#raw_ident)))#method_name #register
         }
     };
 
@@ -66,31 +124,61 @@ pub fn vm_handle(args: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 /// Register wasm's ABI for handling requests.
-/// format description: `#[wasm_handle(i32)] or #[wasm_handle(method=i32)]`
+/// format description: `#[wasm_handle(i32)]`, `#[wasm_handle(method=i32)]`,
+/// `#[wasm_handle(name="str")]` or `#[wasm_handle(Enum::VARIANT)]`
 /// example:
 /// ```
 /// #[wasm_handle(method=123)]
 /// fn xxx<W: wasmy_abi::WasmContext<Value>, Value: wasmy_abi::Message, A: wasmy_abi::Message, R: wasmy_abi::Message>(ctx: W, args: A) -> wasmy_abi::Result<R> {todo!()}
 /// ```
+/// or named, which is called by `WasmCaller::call_named("user.create", ..)`
+/// ```
+/// #[wasm_handle(name="user.create")]
+/// fn yyy<W: wasmy_abi::WasmContext<Value>, Value: wasmy_abi::Message, A: wasmy_abi::Message, R: wasmy_abi::Message>(ctx: W, args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or with a protobuf enum variant (or an `i32` constant), whose value is the
+/// method like `#[vm_handle]`, which is called by
+/// `WasmCaller::call_enum(MyMethods::Add, ..)`
+/// ```
+/// #[wasm_handle(MyMethods::Add)]
+/// fn zzz<W: wasmy_abi::WasmContext<Value>, Value: wasmy_abi::Message, A: wasmy_abi::Message, R: wasmy_abi::Message>(ctx: W, args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
+/// or with a codec other than protobuf like `#[vm_handle]`, which is called by
+/// `WasmCaller::call_with::<JsonCodec, _, _>(123, ..)`
+/// ```
+/// #[wasm_handle(123, codec = "json")]
+/// fn sss<W: wasmy_abi::WasmContext<Value>, Value: wasmy_abi::Message, A: serde::de::DeserializeOwned, R: serde::Serialize>(ctx: W, args: A) -> wasmy_abi::Result<R> {todo!()}
+/// ```
 /// command to check expanded code: `cargo +nightly rustc -- -Zunstable-options
 /// --pretty=expanded`
 #[proc_macro_attribute]
 #[cfg(not(test))] // Work around for rust-lang/rust#62127
 pub fn wasm_handle(args: TokenStream, item: TokenStream) -> TokenStream {
     // println!("{:?}", args);
-    let method = parse_method("wasm_handle", args).unwrap();
+    let attr = parse_method_attr("wasm_handle", args, false).unwrap();
+    let codec = match attr.codec(quote! {::wasmy_abi}) {
+        Ok(codec) => codec,
+        Err(e) => return TokenStream::from(e.to_compile_error()),
+    };
+    let protobuf = matches!(attr.codec.as_deref(), None | Some("protobuf"));
+    let method = attr.method;
     let mut new_item = item.clone();
     let raw_sig = syn::parse_macro_input!(item as ItemFn).sig;
-    let (inner_ident, inner_item) = wasm_gen_inner(raw_sig);
-    let outer_ident = Ident::new(&format!("_wasmy_wasm_handle_{}", method), Span::call_site());
+    let symbol = method.symbol("_wasmy_wasm", "");
+    let types_item = wasm_gen_types(&symbol, &raw_sig, protobuf);
+    let enum_item = wasm_gen_enum_record(&method, &symbol);
+    let (inner_ident, inner_item) = wasm_gen_inner(raw_sig, codec);
+    let outer_ident = Ident::new(&symbol, Span::call_site());
     let outer_item = quote! {
         #[allow(redundant_semicolons)]
         #[inline]
         #[no_mangle]
-        pub extern "C" fn #outer_ident(ctx_size: i32, args_size: i32) {
+        pub extern "C" fn #outer_ident(ctx_size: i32, offset: i32, args_size: i32) {
             #inner_item;
-            ::wasmy_abi::wasm_handle(ctx_size, args_size, #inner_ident)
+            ::wasmy_abi::wasm_handle(ctx_size, offset, args_size, #inner_ident)
         }
+        #types_item
+        #enum_item
     };
     new_item.extend(TokenStream::from(outer_item));
 
@@ -100,7 +188,137 @@ pub fn wasm_handle(args: TokenStream, item: TokenStream) -> TokenStream {
     new_item
 }
 
-fn wasm_gen_inner(raw_sig: Signature) -> (Ident, proc_macro2::TokenStream) {
+/// Register wasm's ABI for handling raw bytes requests, which skip the
+/// protobuf envelope and the buffers of the vm context.
+/// format description: the same as `#[wasm_handle]`, without `codec`
+/// example, which is called by `WasmCaller::call_raw_bytes(123, ..)`:
+/// ```
+/// #[wasm_raw_handle(123)]
+/// fn xxx(ctx: wasmy_abi::WasmCtx, args: Vec<u8>) -> wasmy_abi::Result<Vec<u8>> {todo!()}
+/// ```
+/// command to check expanded code: `cargo +nightly rustc -- -Zunstable-options
+/// --pretty=expanded`
+#[proc_macro_attribute]
+#[cfg(not(test))] // Work around for rust-lang/rust#62127
+pub fn wasm_raw_handle(args: TokenStream, item: TokenStream) -> TokenStream {
+    let prefix = "_wasmy_wasm_raw";
+    wasm_gen_kind_handle("wasm_raw_handle", prefix, args, item, |outer_ident, raw_ident| {
+        quote! {
+            pub extern "C" fn #outer_ident(offset: i32, size: i32) -> i64 {
+                ::wasmy_abi::wasm_raw_handle(offset, size, #raw_ident)
+            }
+        }
+    })
+}
+
+/// Register wasm's ABI for handling stream requests, which pull the input
+/// chunks and emit the output chunks one at a time.
+/// format description: the same as `#[wasm_handle]`, without `codec`
+/// example, which is called by `WasmCaller::call_stream(123, ..)`:
+/// ```
+/// #[wasm_stream_handle(123)]
+/// fn xxx(ctx: wasmy_abi::WasmCtx, input: wasmy_abi::StreamReader, output: wasmy_abi::StreamWriter) -> wasmy_abi::Result<()> {todo!()}
+/// ```
+/// command to check expanded code: `cargo +nightly rustc -- -Zunstable-options
+/// --pretty=expanded`
+#[proc_macro_attribute]
+#[cfg(not(test))] // Work around for rust-lang/rust#62127
+pub fn wasm_stream_handle(args: TokenStream, item: TokenStream) -> TokenStream {
+    let prefix = "_wasmy_wasm_stream";
+    wasm_gen_kind_handle("wasm_stream_handle", prefix, args, item, |outer_ident, raw_ident| {
+        quote! {
+            pub extern "C" fn #outer_ident(ctx_size: i32) {
+                ::wasmy_abi::wasm_stream_handle(ctx_size, #raw_ident)
+            }
+        }
+    })
+}
+
+/// Export the handler that is not of `#[wasm_handle]`, which takes the bytes
+/// as they are, through the underlying function of wasmy-abi.
+fn wasm_gen_kind_handle<F>(
+    macro_name: &str,
+    prefix: &str,
+    args: TokenStream,
+    item: TokenStream,
+    export: F,
+) -> TokenStream
+where
+    F: FnOnce(Ident, Ident) -> proc_macro2::TokenStream,
+{
+    let attr = match parse_method_attr(macro_name, args, false) {
+        Ok(attr) => attr,
+        Err(e) => return TokenStream::from(e.to_compile_error()),
+    };
+    if attr.codec.is_some() {
+        let msg = format!("#[{}] takes the bytes as they are, without a codec", macro_name);
+        return TokenStream::from(syn::Error::new(Span::call_site(), msg).to_compile_error());
+    }
+    let mut new_item = item.clone();
+    let raw_ident = syn::parse_macro_input!(item as ItemFn).sig.ident;
+    let symbol = attr.method.symbol(prefix, "");
+    let enum_item = wasm_gen_enum_record(&attr.method, &symbol);
+    let outer_ident = Ident::new(&symbol, Span::call_site());
+    let export_item = export(outer_ident, raw_ident);
+    let outer_item = quote! {
+        #[inline]
+        #[no_mangle]
+        #export_item
+        #enum_item
+    };
+    new_item.extend(TokenStream::from(outer_item));
+
+    #[cfg(debug_assertions)]
+    println!("{}", new_item);
+
+    new_item
+}
+
+/// Collect the message types of the method for `_wasmy_manifest`, which
+/// exports their protobuf full names. A type is unknown if it is not a
+/// concrete type or not of protobuf.
+fn wasm_gen_types(symbol: &str, raw_sig: &Signature, protobuf: bool) -> proc_macro2::TokenStream {
+    let type_name = |ty: Option<&Type>| match ty.filter(|ty| protobuf && is_concrete(ty, raw_sig)) {
+        Some(ty) => quote! { Some(::wasmy_abi::message_full_name::<#ty>) },
+        None => quote! { None },
+    };
+    let args = type_name(match raw_sig.inputs.last() {
+        Some(FnArg::Typed(a)) => Some(&a.ty),
+        _ => None,
+    });
+    let rets = type_name(match &raw_sig.output {
+        // the R of `Result<R>`
+        syn::ReturnType::Type(_, ty) => match ty.deref() {
+            Type::Path(p) => match &p.path.segments.last().unwrap().arguments {
+                syn::PathArguments::AngleBracketed(a) => match a.args.first() {
+                    Some(syn::GenericArgument::Type(ty)) => Some(ty),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        syn::ReturnType::Default => None,
+    });
+    quote! {
+        ::wasmy_abi::submit_types! {
+            ::wasmy_abi::WasmHandlerTypes::new(#symbol, #args, #rets)
+        }
+    }
+}
+
+/// Whether the type is a path that is not a generic parameter of the function.
+fn is_concrete(ty: &Type, raw_sig: &Signature) -> bool {
+    match ty {
+        Type::Path(p) => !raw_sig.generics.type_params().any(|t| p.path.is_ident(&t.ident)),
+        _ => false,
+    }
+}
+
+fn wasm_gen_inner(
+    raw_sig: Signature,
+    codec: proc_macro2::TokenStream,
+) -> (Ident, proc_macro2::TokenStream) {
     let inner_ident = Ident::new("_inner", Span::call_site());
     let raw_ident = raw_sig.ident.clone();
     let raw_first_input = raw_sig.inputs.first().unwrap();
@@ -111,7 +329,7 @@ fn wasm_gen_inner(raw_sig: Signature) -> (Ident, proc_macro2::TokenStream) {
             #[allow(unused_mut)]
             #[inline]
             fn #inner_ident(#raw_first_input, args: ::wasmy_abi::InArgs) -> ::wasmy_abi::Result<::wasmy_abi::Any> {
-               ::wasmy_abi::pack_any(#raw_ident(#fn_args, args.get_args()?)?)
+               ::wasmy_abi::pack_with::<#codec, _>(#raw_ident(#fn_args, args.get_args_with::<#codec, _>()?)?)
             }
         },
     )
@@ -147,18 +365,185 @@ pub fn wasm_onload(_args: TokenStream, item: TokenStream) -> TokenStream {
     TokenStream::from(new_item)
 }
 
-fn parse_method(marco_name: &str, input: TokenStream) -> Result<i32, syn::Error> {
+/// Declare the vm methods that the wasm calls, which are verified when the vm
+/// loads the wasm, failing with `CODE_EXPORTS` if any has no handler.
+/// format description: `wasm_requires!(i32, name="str", Enum::VARIANT, ..)`
+/// example:
+/// ```
+/// wasm_requires!(1, 2, name = "kv.get", MyMethods::Get);
+/// ```
+#[proc_macro]
+#[cfg(not(test))] // Work around for rust-lang/rust#62127
+pub fn wasm_requires(input: TokenStream) -> TokenStream {
+    let new_item = match wasm_gen_requires(input) {
+        Ok(new_item) => new_item,
+        Err(e) => return TokenStream::from(e.to_compile_error()),
+    };
+    #[cfg(debug_assertions)]
+    println!("{}", new_item);
+    TokenStream::from(new_item)
+}
+
+/// Record each method into the `wasmy.requires` custom section, as the i32
+/// method, the u16 length of the label, and the label, in little endian.
+fn wasm_gen_requires(input: TokenStream) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let err = syn::Error::new_spanned(
+        proc_macro2::TokenStream::from(input.clone()),
+        "wasm_requires!(i32, name=\"str\", Enum::VARIANT, ..)",
+    );
+    let attr = AttributeArgs::parse_terminated.parse(input).or(Err(err))?;
+    let mut items = vec![];
+    for arg in attr {
+        let (method, label) = match arg {
+            syn::NestedMeta::Lit(lit) => {
+                let id = parse_method_lit(&lit).ok_or_else(|| {
+                    syn::Error::new_spanned(lit, "method is not i32 greater than or equal to 0")
+                })?;
+                (quote! {#id}, String::new())
+            }
+            syn::NestedMeta::Meta(syn::Meta::NameValue(namevalue))
+                if namevalue.path.is_ident("name") =>
+            {
+                match &namevalue.lit {
+                    Lit::Str(name) if !name.value().is_empty() => {
+                        (quote! {::wasmy_abi::method_id(#name)}, name.value())
+                    }
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            namevalue,
+                            "attribute name is not a non-empty string",
+                        ));
+                    }
+                }
+            }
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) => {
+                let label = path_label(&path);
+                (quote! {#path as ::wasmy_abi::Method}, label)
+            }
+            other => {
+                return Err(syn::Error::new_spanned(other, "Unknown method inside the macro"));
+            }
+        };
+        let len = label.len();
+        let label = proc_macro2::Literal::byte_string(label.as_bytes());
+        // an anonymous scope for each, so that the macro can be used many times
+        items.push(quote! {
+            #[cfg(target_family = "wasm")]
+            const _: () = {
+                #[allow(dead_code)]
+                #[repr(C)]
+                struct Requirement {
+                    method: [u8; 4],
+                    len: [u8; 2],
+                    label: [u8; #len],
+                }
+                #[link_section = "wasmy.requires"]
+                #[used]
+                static REQUIREMENT: Requirement = Requirement {
+                    method: (#method).to_le_bytes(),
+                    len: (#len as u16).to_le_bytes(),
+                    label: *#label,
+                };
+            };
+        });
+    }
+    Ok(quote! {#(#items)*})
+}
+
+/// The method of a handler, a numeric id, a name, or the path of an enum
+/// variant or an `i32` constant with its label `Enum::VARIANT`, whose value is
+/// the method.
+enum MethodKey {
+    Id(i32),
+    Name(String),
+    Enum(syn::Path, String),
+}
+
+impl MethodKey {
+    /// The symbol of the generated function, e.g. `_wasmy_wasm_handle_1`,
+    /// `_wasmy_wasm_named_user_2ecreate` or `_wasmy_wasm_enum_Methods_3a_3aAdd`.
+    fn symbol(&self, prefix: &str, infix: &str) -> String {
+        match self {
+            MethodKey::Id(id) => format!("{}_handle{}_{}", prefix, infix, id),
+            MethodKey::Name(name) => {
+                format!("{}_named{}_{}", prefix, infix, mangle_method_name(name))
+            }
+            MethodKey::Enum(_, label) => {
+                format!("{}_enum{}_{}", prefix, infix, mangle_method_name(label))
+            }
+        }
+    }
+}
+
+/// The attribute of a handler macro.
+struct HandleAttr {
+    method: MethodKey,
+    set: Option<String>,
+    codec: Option<String>,
+}
+
+impl HandleAttr {
+    /// The path of the codec type: a builtin one by its name, or a custom one
+    /// by its path, `ProtobufCodec` if none.
+    fn codec(
+        &self,
+        krate: proc_macro2::TokenStream,
+    ) -> Result<proc_macro2::TokenStream, syn::Error> {
+        let codec = match self.codec.as_deref() {
+            None | Some("protobuf") => quote! {#krate::ProtobufCodec},
+            Some("raw") => quote! {#krate::RawCodec},
+            Some("json") => quote! {#krate::JsonCodec},
+            Some("msgpack") => quote! {#krate::MsgPackCodec},
+            Some("postcard") => quote! {#krate::PostcardCodec},
+            Some(path) => {
+                let path = syn::parse_str::<syn::Path>(path)?;
+                quote! {#path}
+            }
+        };
+        Ok(codec)
+    }
+}
+
+/// Parse `i32`, `method=i32`, `name="str"`, `Enum::VARIANT`, `codec="name"`, and
+/// `set="name"` if it is allowed.
+fn parse_method_attr(
+    marco_name: &str,
+    input: TokenStream,
+    allow_set: bool,
+) -> Result<HandleAttr, syn::Error> {
     let method = input.to_string().parse::<i32>().unwrap_or(-1);
     if method >= 0 {
-        return Ok(method);
+        return Ok(HandleAttr { method: MethodKey::Id(method), set: None, codec: None });
     }
     let err = syn::Error::new_spanned(
         proc_macro2::TokenStream::from(input.clone()),
-        format!("#[{0}(i32)] or #[{0}(method=i32)]", marco_name),
+        if allow_set {
+            format!(
+                "#[{0}(i32)], #[{0}(method=i32)], #[{0}(name=\"str\")] or #[{0}(Enum::VARIANT)], with optional set=\"name\" and codec=\"name\"",
+                marco_name
+            )
+        } else {
+            format!(
+                "#[{0}(i32)], #[{0}(method=i32)], #[{0}(name=\"str\")] or #[{0}(Enum::VARIANT)], with optional codec=\"name\"",
+                marco_name
+            )
+        },
     );
     let attr = AttributeArgs::parse_terminated.parse(input).or(Err(err.clone()))?;
+    let mut method = None;
+    let mut set = None;
+    let mut codec = None;
     for arg in attr {
         match arg {
+            syn::NestedMeta::Lit(lit) => {
+                method = Some(MethodKey::Id(parse_method_lit(&lit).ok_or_else(|| {
+                    syn::Error::new_spanned(lit, "method is not i32 greater than or equal to 0")
+                })?));
+            }
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) => {
+                let label = path_label(&path);
+                method = Some(MethodKey::Enum(path, label));
+            }
             syn::NestedMeta::Meta(syn::Meta::NameValue(namevalue)) => {
                 let ident = namevalue
                     .path
@@ -170,21 +555,56 @@ fn parse_method(marco_name: &str, input: TokenStream) -> Result<i32, syn::Error>
                     .to_lowercase();
                 match ident.as_str() {
                     "method" => {
-                        if let Lit::Int(i) = &namevalue.lit {
-                            if let Ok(method) = i.base10_digits().parse::<i32>() {
-                                if method >= 0 {
-                                    return Ok(method);
-                                }
-                            }
+                        method = Some(MethodKey::Id(parse_method_lit(&namevalue.lit).ok_or_else(
+                            || {
+                                syn::Error::new_spanned(
+                                    &namevalue,
+                                    "attribute method is not i32 greater than or equal to 0",
+                                )
+                            },
+                        )?));
+                    }
+                    "name" => match &namevalue.lit {
+                        Lit::Str(name) if !name.value().is_empty() => {
+                            method = Some(MethodKey::Name(name.value()));
+                        }
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                namevalue,
+                                "attribute name is not a non-empty string",
+                            ));
+                        }
+                    },
+                    "set" if allow_set => {
+                        if let Lit::Str(name) = &namevalue.lit {
+                            set = Some(name.value());
+                        } else {
+                            return Err(syn::Error::new_spanned(
+                                namevalue,
+                                "attribute set is not a string",
+                            ));
+                        }
+                    }
+                    "codec" => {
+                        if let Lit::Str(name) = &namevalue.lit {
+                            codec = Some(name.value());
+                        } else {
+                            return Err(syn::Error::new_spanned(
+                                namevalue,
+                                "attribute codec is not a string",
+                            ));
                         }
-                        return Err(syn::Error::new_spanned(
-                            namevalue,
-                            "attribute method is not i32 greater than or equal to 0",
-                        ));
                     }
                     name => {
-                        let msg =
-                            format!("Unknown attribute {} is specified; expected `method`", name,);
+                        let expected = if allow_set {
+                            "`method`, `name`, `set` or `codec`"
+                        } else {
+                            "`method`, `name` or `codec`"
+                        };
+                        let msg = format!(
+                            "Unknown attribute {} is specified; expected {}",
+                            name, expected
+                        );
                         return Err(syn::Error::new_spanned(namevalue, msg));
                     }
                 }
@@ -194,7 +614,112 @@ fn parse_method(marco_name: &str, input: TokenStream) -> Result<i32, syn::Error>
             }
         }
     }
-    Err(err)
+    method.map(|method| HandleAttr { method, set, codec }).ok_or(err)
+}
+
+/// The label of the method path as written, e.g. `Methods::Add`.
+fn path_label(path: &syn::Path) -> String {
+    let segments: Vec<String> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+    segments.join("::")
+}
+
+/// Record the value of the method path into the `wasmy.enums` custom section,
+/// as the i32 method, the u16 length of the symbol, and the symbol exported
+/// for it, in little endian. The value is only known to the compiler, so the
+/// vm finds the handler of the method by the record.
+fn wasm_gen_enum_record(method: &MethodKey, symbol: &str) -> proc_macro2::TokenStream {
+    let path = match method {
+        MethodKey::Enum(path, _) => path,
+        _ => return quote! {},
+    };
+    let len = symbol.len();
+    let symbol = proc_macro2::Literal::byte_string(symbol.as_bytes());
+    quote! {
+        #[cfg(target_family = "wasm")]
+        const _: () = {
+            #[allow(dead_code)]
+            #[repr(C)]
+            struct EnumMethod {
+                method: [u8; 4],
+                len: [u8; 2],
+                symbol: [u8; #len],
+            }
+            #[link_section = "wasmy.enums"]
+            #[used]
+            static ENUM_METHOD: EnumMethod = EnumMethod {
+                method: (#path as ::wasmy_abi::Method).to_le_bytes(),
+                len: (#len as u16).to_le_bytes(),
+                symbol: *#symbol,
+            };
+        };
+    }
+}
+
+fn parse_method_lit(lit: &Lit) -> Option<i32> {
+    if let Lit::Int(i) = lit {
+        if let Ok(method) = i.base10_digits().parse::<i32>() {
+            if method >= 0 {
+                return Some(method);
+            }
+        }
+    }
+    None
+}
+
+/// The arguments to call the `#[vm_handle]` function with: the leading ones
+/// are the context `Option<&C>` or the host state `#[state] &mut S`, and the
+/// last one is the args. The `#[state]` markers are removed from the function.
+fn vm_call_args(
+    sig: &mut Signature,
+    codec: &proc_macro2::TokenStream,
+) -> Result<Vec<proc_macro2::TokenStream>, syn::Error> {
+    let mut call_args = vec![];
+    let mut has_state = false;
+    let len = sig.inputs.len();
+    for (i, input) in sig.inputs.iter_mut().enumerate() {
+        let a = match input {
+            FnArg::Typed(a) => a,
+            FnArg::Receiver(_) => {
+                return Err(syn::Error::new_spanned(input, "#[vm_handle] must be a free function"));
+            }
+        };
+        let marked = a.attrs.iter().position(|attr| attr.path.is_ident("state"));
+        let marker = marked.map(|pos| a.attrs.remove(pos));
+        let is_mut = matches!(a.ty.deref(), Type::Reference(r) if r.mutability.is_some());
+        if i + 1 == len {
+            if let Some(marker) = marker {
+                return Err(syn::Error::new_spanned(marker, "the last parameter is the args"));
+            }
+            call_args.push(quote! {::wasmy_vm::unpack_with::<#codec, _>(args)?});
+            break;
+        }
+        let is_state = match (marker, is_mut) {
+            (Some(_), true) => true,
+            (None, false) => false,
+            (Some(marker), false) => {
+                return Err(syn::Error::new_spanned(marker, "#[state] is only for `&mut S`"));
+            }
+            (None, true) => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "mark the host state with `#[state] state: &mut S`",
+                ));
+            }
+        };
+        if is_state {
+            if has_state {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "only one host state `&mut S` is allowed",
+                ));
+            }
+            has_state = true;
+            call_args.push(quote! {env.state_mut()?});
+        } else {
+            call_args.push(quote! {::wasmy_vm::VmHandlerApi::try_as(ctx)?});
+        }
+    }
+    Ok(call_args)
 }
 
 fn fn_arg_ident(arg: &FnArg) -> Ident {
@@ -0,0 +1,63 @@
+//! The mangling of the method names into the symbols, shared by the macros
+//! that export the symbols and the vm that finds them.
+
+/// Mangle the method name into a part of a symbol: ASCII letters and digits
+/// are kept, and any other byte is written as `_` and two hex digits.
+pub fn mangle_method_name(name: &str) -> String {
+    let mut mangled = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() {
+            mangled.push(b as char);
+        } else {
+            mangled.push_str(&format!("_{:02x}", b));
+        }
+    }
+    mangled
+}
+
+/// The reverse of `mangle_method_name`, none if it is not mangled by it.
+pub fn demangle_method_name(mangled: &str) -> Option<String> {
+    let bytes = mangled.as_bytes();
+    let mut name = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'_' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            if hex.to_lowercase() != hex {
+                return None;
+            }
+            let b = u8::from_str_radix(hex, 16).ok()?;
+            if b.is_ascii_alphanumeric() {
+                return None;
+            }
+            name.push(b);
+            i += 3;
+        } else if bytes[i].is_ascii_alphanumeric() {
+            name.push(bytes[i]);
+            i += 1;
+        } else {
+            return None;
+        }
+    }
+    if name.is_empty() {
+        return None;
+    }
+    String::from_utf8(name).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{demangle_method_name, mangle_method_name};
+
+    #[test]
+    fn mangle() {
+        assert_eq!(mangle_method_name("user.create"), "user_2ecreate");
+        assert_eq!(mangle_method_name("Methods::Add"), "Methods_3a_3aAdd");
+        assert_eq!(demangle_method_name("user_2ecreate").as_deref(), Some("user.create"));
+        // every name has only one mangled form
+        assert_eq!(demangle_method_name("user_2Ecreate"), None);
+        assert_eq!(demangle_method_name("user_61"), None);
+        assert_eq!(demangle_method_name("user_2"), None);
+        assert_eq!(demangle_method_name(""), None);
+    }
+}
@@ -1,52 +1,92 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+};
+
 use protobuf::{CodedOutputStream, Message};
-use wasmy_abi::{InArgs, OutRets};
+use wasmy_abi::{CodeMsg, InArgs, OutRets, Result, CODE_NONE};
+
+use crate::stream::StreamSlot;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Context {
-    pub(crate) value_ptr: usize,
+    /// The context value of the current call.
+    pub(crate) ctx_value: HostContext,
+    /// The host state of the current call, visible to the vm handlers.
+    pub host: HostContext,
+    /// The host state of the instance, visible to the vm handlers.
+    pub state: HostContext,
     pub value_bytes: Vec<u8>,
     pub swap_memory: Vec<u8>,
+    /// The input and the output of the current stream call, set by its
+    /// `StreamScope`.
+    pub(crate) stream: Option<StreamSlot>,
 }
 
 impl Context {
     pub(crate) fn with_capacity(capacity: usize) -> Self {
         Self {
-            value_ptr: 0,
+            ctx_value: HostContext::default(),
+            host: HostContext::default(),
+            state: HostContext::default(),
             value_bytes: Vec::with_capacity(capacity),
             swap_memory: Vec::with_capacity(capacity),
+            stream: None,
         }
     }
 
-    pub fn set_value_ptr<T>(&mut self, ptr: &T) {
-        self.value_ptr = ptr as *const T as usize;
+    /// Set the context value of the raw call.
+    pub fn set_ctx_value<C: Message>(&mut self, value: C) {
+        write_to_vec(&value, &mut self.value_bytes);
+        self.ctx_value.clear();
+        self.ctx_value.insert(value);
+    }
+
+    /// Set the context value of the raw call.
+    #[deprecated(note = "use `set_ctx_value` instead")]
+    pub fn set_value_ptr<C: Message + Clone>(&mut self, ptr: &C) {
+        self.set_ctx_value(ptr.clone())
     }
 
-    pub unsafe fn value_ptr<T>(&self) -> Option<&T> {
-        let ptr = self.value_ptr as *const T;
-        if ptr.is_null() { None } else { Some(&*ptr) }
+    /// Get the context value of the call.
+    /// NOTE: The value is owned by the context now, `unsafe` is only kept for
+    /// the compatibility.
+    #[deprecated(note = "use `HostEnv::ctx` instead")]
+    pub unsafe fn value_ptr<T: Any>(&self) -> Option<&T> {
+        self.ctx_value.get()
     }
 
     pub(crate) fn set_args<C: Message>(
         &mut self,
-        ctx_value: Option<&C>,
+        ctx_value: Option<C>,
         in_args: InArgs,
     ) -> (usize, usize) {
+        let ctx_size = self.set_ctx(ctx_value);
         let args_size = write_to_vec(&in_args, &mut self.swap_memory);
         if args_size == 0 {
             unsafe { self.swap_memory.set_len(0) }
         }
-        let ctx_size = if let Some(val) = ctx_value {
-            self.value_ptr = val as *const C as usize;
-            write_to_vec(val, &mut self.value_bytes)
-        } else {
-            unsafe { self.value_bytes.set_len(0) };
-            0
-        };
         (ctx_size, args_size)
     }
 
+    /// Set the context value of the call, which the wasm recalls on demand.
+    /// Whatever a previous call left behind is cleared first, as it may have
+    /// failed before the rets were taken.
+    pub(crate) fn set_ctx<C: Message>(&mut self, ctx_value: Option<C>) -> usize {
+        self.reverted();
+        match ctx_value {
+            Some(val) => {
+                let size = write_to_vec(&val, &mut self.value_bytes);
+                self.ctx_value.insert(val);
+                size
+            }
+            None => 0,
+        }
+    }
+
     pub(crate) fn out_rets(&mut self) -> OutRets {
-        let res = if self.swap_memory.len() > 0 {
+        let res = if !self.swap_memory.is_empty() {
             OutRets::parse_from_bytes(self.swap_memory.as_slice()).unwrap()
         } else {
             OutRets::new()
@@ -56,23 +96,87 @@ impl Context {
     }
 
     pub(crate) fn reverted(&mut self) {
+        self.ctx_value.clear();
         unsafe {
-            self.value_ptr = 0;
             self.value_bytes.set_len(0);
             self.swap_memory.set_len(0);
         };
     }
 }
 
+/// The host values keyed by their types, such as the context value of
+/// `ctx_call` and the host state of the handlers.
+#[derive(Default)]
+pub struct HostContext {
+    values: HashMap<TypeId, (Box<dyn Any + Send>, &'static str)>,
+}
+
+impl HostContext {
+    /// Insert the value, returning the previous value of the same type.
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), (Box::new(value), type_name::<T>()))
+            .and_then(|(old, _)| old.downcast().ok().map(|old| *old))
+    }
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|(v, _)| v.downcast_ref())
+    }
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>()).and_then(|(v, _)| v.downcast_mut())
+    }
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.values.remove(&TypeId::of::<T>()).and_then(|(v, _)| v.downcast().ok().map(|v| *v))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.values.clear()
+    }
+    /// The type names of the values.
+    pub fn type_names(&self) -> Vec<&'static str> {
+        self.values.values().map(|(_, name)| *name).collect()
+    }
+}
+
+impl Debug for HostContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.type_names()).finish()
+    }
+}
+
+/// The host values visible to a vm handler during a call.
+pub struct HostEnv<'a> {
+    /// The context value of the call.
+    pub ctx: &'a HostContext,
+    /// The host state of the call.
+    pub call: &'a mut HostContext,
+    /// The host state of the instance.
+    pub instance: &'a mut HostContext,
+}
+
+impl<'a> HostEnv<'a> {
+    /// Get the host state of the type, looking up the state of the call first
+    /// and then the state of the instance.
+    pub fn state_mut<T: Any>(&mut self) -> Result<&mut T> {
+        if self.call.get::<T>().is_some() {
+            return Ok(self.call.get_mut().unwrap());
+        }
+        self.instance.get_mut().ok_or_else(|| {
+            CodeMsg::new(CODE_NONE, format!("host state not found: {}", type_name::<T>()))
+        })
+    }
+}
+
 pub(crate) fn write_to_vec(msg: &dyn Message, buffer: &mut Vec<u8>) -> usize {
     let size = msg.compute_size() as usize;
     resize_with_capacity(buffer, size);
     write_to_with_cached_sizes(msg, buffer)
 }
 
-fn write_to_with_cached_sizes(msg: &dyn Message, buffer: &mut Vec<u8>) -> usize {
+fn write_to_with_cached_sizes(msg: &dyn Message, buffer: &mut [u8]) -> usize {
     let mut os = CodedOutputStream::bytes(buffer);
-    msg.write_to_with_cached_sizes(&mut os).or_else(|e| Err(format!("{}", e))).unwrap();
+    msg.write_to_with_cached_sizes(&mut os).map_err(|e| format!("{}", e)).unwrap();
     // os.flush().unwrap();
     buffer.len()
 }
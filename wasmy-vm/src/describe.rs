@@ -0,0 +1,206 @@
+use wasmer::{ExternType, Module};
+use wasmy_abi::*;
+
+use crate::{
+    handler::WasmHandlerApi,
+    interrupt::INTERRUPT_IMPORT,
+    limits::ResourceLimits,
+    manifest::{Manifest, MethodTypes, RequiredMethod},
+    WasmUri,
+};
+
+/// The interface of a loaded wasm module.
+#[derive(Clone, Debug)]
+pub struct ModuleDescription {
+    pub wasm_uri: WasmUri,
+    /// The wasm methods exported by `#[wasm_handle]`, `#[wasm_raw_handle]` and
+    /// `#[wasm_stream_handle]`, sorted by method.
+    pub methods: Vec<WasmMethodInfo>,
+    /// Whether `#[wasm_onload]` is exported.
+    pub has_onload: bool,
+    /// The imports that the module requires.
+    pub imports: Vec<ImportInfo>,
+    /// The linear memories defined or imported by the module.
+    pub memories: Vec<MemoryInfo>,
+    /// The limits that the module is loaded with.
+    pub limits: ResourceLimits,
+    /// The vm methods that the wasm requires.
+    pub requires: Vec<RequiredMethod>,
+    /// The custom sections, in the order of the binary.
+    pub custom_sections: Vec<CustomSection>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WasmMethodInfo {
+    pub method: WasmMethod,
+    /// The name of the method exported by `#[wasm_handle(name = "...")]`.
+    pub name: Option<String>,
+    /// The exported symbol.
+    pub symbol: String,
+    /// The message types recorded in the manifest section.
+    pub types: Option<MethodTypes>,
+    /// Whether it is a raw bytes handler exported by `#[wasm_raw_handle]`.
+    pub raw: bool,
+    /// Whether it is a stream handler exported by `#[wasm_stream_handle]`.
+    pub stream: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportInfo {
+    pub module: String,
+    pub name: String,
+    pub ty: ExternType,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryInfo {
+    /// The export or import name of the memory.
+    pub name: String,
+    pub imported: bool,
+    pub minimum_pages: u32,
+    pub maximum_pages: Option<u32>,
+    pub shared: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CustomSection {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl ModuleDescription {
+    /// Describe the compiled module, with the custom sections read from the
+    /// wasm bytes if they are still registered.
+    pub(crate) fn new(
+        wasm_uri: &WasmUri,
+        module: &Module,
+        manifest: &Manifest,
+        wasm_bytes: Option<&[u8]>,
+        limits: &ResourceLimits,
+    ) -> Result<ModuleDescription> {
+        let mut methods = vec![];
+        let mut has_onload = false;
+        for function in module.exports().functions() {
+            let symbol = function.name();
+            let raw_symbol = WasmHandlerApi::from_raw_symbol(symbol);
+            let stream_symbol = WasmHandlerApi::from_stream_symbol(symbol);
+            let method_symbol =
+                raw_symbol.as_deref().or(stream_symbol.as_deref()).unwrap_or(symbol);
+            if symbol == WasmHandlerApi::onload_symbol() {
+                has_onload = true;
+            } else if let Some(name) = WasmHandlerApi::symbol_to_name(method_symbol) {
+                methods.push(WasmMethodInfo {
+                    method: method_id(&name),
+                    name: Some(name),
+                    symbol: symbol.to_string(),
+                    types: manifest.get(symbol).cloned(),
+                    raw: raw_symbol.is_some(),
+                    stream: stream_symbol.is_some(),
+                });
+            } else if let Some(method) = WasmHandlerApi::symbol_to_method(method_symbol)
+                .or_else(|| manifest.enum_method(symbol))
+            {
+                methods.push(WasmMethodInfo {
+                    method,
+                    name: None,
+                    symbol: symbol.to_string(),
+                    types: manifest.get(symbol).cloned(),
+                    raw: raw_symbol.is_some(),
+                    stream: stream_symbol.is_some(),
+                });
+            }
+        }
+        methods.sort_by_key(|m| m.method);
+        // the import added by the vm is not required by the wasm
+        let imports = module
+            .imports()
+            .filter(|i| !(i.module() == "env" && i.name() == INTERRUPT_IMPORT))
+            .map(|i| ImportInfo {
+                module: i.module().to_string(),
+                name: i.name().to_string(),
+                ty: i.ty().clone(),
+            })
+            .collect();
+        let memories = module
+            .imports()
+            .memories()
+            .map(|m| (m.name().to_string(), true, *m.ty()))
+            .chain(module.exports().memories().map(|m| (m.name().to_string(), false, *m.ty())))
+            .map(|(name, imported, ty)| MemoryInfo {
+                name,
+                imported,
+                minimum_pages: ty.minimum.0,
+                maximum_pages: ty.maximum.map(|p| p.0),
+                shared: ty.shared,
+            })
+            .collect();
+        Ok(ModuleDescription {
+            wasm_uri: wasm_uri.clone(),
+            methods,
+            has_onload,
+            imports,
+            memories,
+            limits: limits.clone(),
+            requires: manifest.requires().to_vec(),
+            custom_sections: match wasm_bytes {
+                Some(wasm_bytes) => custom_sections(wasm_bytes)?,
+                None => vec![],
+            },
+        })
+    }
+}
+
+/// Read the custom sections of the wasm binary, skipping the others.
+pub(crate) fn custom_sections(wasm: &[u8]) -> Result<Vec<CustomSection>> {
+    let malformed = || CodeMsg::new(CODE_COMPILE, "malformed wasm binary");
+    if wasm.len() < 8 || &wasm[..4] != b"\0asm" {
+        return Err(malformed());
+    }
+    let mut sections = vec![];
+    let mut pos = 8;
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let size = read_leb128_u32(wasm, &mut pos).ok_or_else(malformed)? as usize;
+        let end = pos.checked_add(size).filter(|end| *end <= wasm.len()).ok_or_else(malformed)?;
+        if id == 0 {
+            let name_len = read_leb128_u32(wasm, &mut pos).ok_or_else(malformed)? as usize;
+            let name_end = pos.checked_add(name_len).filter(|e| *e <= end).ok_or_else(malformed)?;
+            let name = std::str::from_utf8(&wasm[pos..name_end]).map_err(|_| malformed())?;
+            let data = wasm[name_end..end].to_vec();
+            sections.push(CustomSection { name: name.to_string(), data });
+        }
+        pos = end;
+    }
+    Ok(sections)
+}
+
+fn read_leb128_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let b = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= ((b & 0x7f) as u32).checked_shl(shift)?;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::custom_sections;
+
+    #[test]
+    fn read_custom_sections() {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // an empty type section, then the custom section "ab" with data [7, 8]
+        wasm.extend_from_slice(&[1, 1, 0, 0, 5, 2, b'a', b'b', 7, 8]);
+        let sections = custom_sections(&wasm).unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].name, "ab");
+        assert_eq!(sections[0].data, vec![7, 8]);
+        assert!(custom_sections(&wasm[..wasm.len() - 1]).is_err());
+    }
+}
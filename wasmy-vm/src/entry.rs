@@ -1,8 +1,17 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
 use wasmer::Value;
 use wasmy_abi::*;
 
 use crate::{
-    context::Context, wasm_file::WasmFile, FnBuildImports, FnCheckModule, Instance, WasmUri,
+    context::{Context, HostContext},
+    describe::ModuleDescription,
+    interrupt::CancelHandle,
+    limits::ResourceLimits,
+    module::{MeteringConfig, ModuleCacheStats},
+    pool::{InstancePool, PoolConfig, PoolMetrics},
+    wasm_file::WasmFile,
+    FnBuildImports, FnCheckModule, FnInitState, HandlerSet, Instance, WasmHandlerApi, WasmUri,
 };
 
 pub fn load_wasm<B, W>(wasm_file: W) -> Result<WasmCaller>
@@ -10,7 +19,7 @@ where
     B: AsRef<[u8]>,
     W: WasmFile<B>,
 {
-    custom_load_wasm(wasm_file, None, None)
+    load_wasm_with(wasm_file, LoadOptions::default())
 }
 
 pub fn custom_load_wasm<B, W>(
@@ -22,7 +31,61 @@ where
     B: AsRef<[u8]>,
     W: WasmFile<B>,
 {
-    Ok(WasmCaller(Instance::install(wasm_file, check_module, build_imports)?))
+    load_wasm_with(wasm_file, LoadOptions { check_module, build_imports, ..Default::default() })
+}
+
+/// Reload the wasm file with the options its previous version was loaded with.
+/// The in-flight calls finish on the previous version, and the callers holding a
+/// `WasmCaller` of the URI use the new version on their next call.
+/// NOTE: It must not be called from a handler of a call to the same wasm.
+pub fn reload_wasm<B, W>(wasm_file: W) -> Result<WasmCaller>
+where
+    B: AsRef<[u8]>,
+    W: WasmFile<B>,
+{
+    Ok(WasmCaller(Instance::reinstall(wasm_file)?))
+}
+
+/// Unload the wasm file, dropping all of its instances after the in-flight
+/// calls finish.
+/// NOTE: It must not be called from a handler of a call to the same wasm.
+pub fn unload_wasm(wasm_uri: &WasmUri) -> Result<()> {
+    Instance::uninstall(wasm_uri)
+}
+
+/// Options of loading a wasm module, shared by all the instances of it.
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    /// Check the compiled module before instantiation.
+    pub check_module: Option<FnCheckModule>,
+    /// Build the imports of the instances instead of the default WASI imports.
+    pub build_imports: Option<FnBuildImports>,
+    /// Sizing of the instance pool.
+    pub pool: PoolConfig,
+    /// Directory of the on-disk precompiled artifact cache, disabled if none.
+    /// The artifacts are loaded as native code without validation, so the
+    /// directory must be trusted and writable by nobody else.
+    pub artifact_cache: Option<PathBuf>,
+    /// Instruction metering of the calls, disabled if none.
+    pub metering: Option<MeteringConfig>,
+    /// Compile the checks that let `call_with_timeout` and `call_with_cancel`
+    /// interrupt the running calls, which cost each function entry and each
+    /// loop head a few instructions, disabled by default.
+    pub interruptible: bool,
+    /// Limits of the memory and tables of each instance.
+    pub limits: ResourceLimits,
+    /// Initialize the host state of each instance.
+    pub init_state: Option<FnInitState>,
+    /// The vm handlers of the wasm, the global handlers are used if none.
+    pub handlers: Option<Arc<HandlerSet>>,
+}
+
+pub fn load_wasm_with<B, W>(wasm_file: W, options: LoadOptions) -> Result<WasmCaller>
+where
+    B: AsRef<[u8]>,
+    W: WasmFile<B>,
+{
+    Ok(WasmCaller(Instance::install(wasm_file, options)?))
 }
 
 #[derive(Clone, Hash, Eq, PartialEq, Debug)]
@@ -48,9 +111,67 @@ impl WasmCaller {
     }
     /// Call the wasm specified method.
     pub fn call<A: Message, R: Message>(&self, method: Method, data: A) -> Result<R> {
+        self.check_types::<A, R>(&WasmHandlerApi::method_to_symbol(method))?;
         let in_args = InArgs::try_new(method, data)?;
         Instance::with(self.0.clone(), |ins| -> Result<R> { ins.handle_wasm(in_args)?.into() })
     }
+    /// Call the wasm specified method with the fuel budget, returning the
+    /// remaining fuel. The call fails with `CODE_FUEL` if the fuel runs out.
+    /// NOTE: The wasm must be loaded with metering.
+    pub fn call_with_fuel<A: Message, R: Message>(
+        &self,
+        method: Method,
+        data: A,
+        budget: u64,
+    ) -> Result<(R, u64)> {
+        self.check_types::<A, R>(&WasmHandlerApi::method_to_symbol(method))?;
+        let in_args = InArgs::try_new(method, data)?;
+        Instance::with(self.0.clone(), |ins| -> Result<(R, u64)> {
+            ins.set_fuel(budget)?;
+            let rets: Result<R> = ins.handle_wasm(in_args)?.into();
+            Ok((rets?, ins.remaining_fuel().unwrap_or(0)))
+        })
+    }
+    /// Call the wasm specified method with the host state of the call, which
+    /// the handlers can modify.
+    pub fn call_with_state<A: Message, R: Message>(
+        &self,
+        method: Method,
+        data: A,
+        state: &mut HostContext,
+    ) -> Result<R> {
+        self.check_types::<A, R>(&WasmHandlerApi::method_to_symbol(method))?;
+        let in_args = InArgs::try_new(method, data)?;
+        Instance::with(self.0.clone(), |ins| -> Result<R> {
+            ins.with_call_state(state, |ins| ins.handle_wasm(in_args)?.into())
+        })
+    }
+    /// Call the wasm specified method, which fails with `CODE_TIMEOUT` if it
+    /// does not return within the timeout.
+    /// NOTE: The wasm must be loaded interruptible.
+    pub fn call_with_timeout<A: Message, R: Message>(
+        &self,
+        method: Method,
+        data: A,
+        timeout: Duration,
+    ) -> Result<R> {
+        self.call_with_cancel(method, data, &CancelHandle::with_timeout(timeout))
+    }
+    /// Call the wasm specified method, which fails with `CODE_CANCELED` once
+    /// the handle is canceled from another thread.
+    /// NOTE: The wasm must be loaded interruptible.
+    pub fn call_with_cancel<A: Message, R: Message>(
+        &self,
+        method: Method,
+        data: A,
+        handle: &CancelHandle,
+    ) -> Result<R> {
+        self.check_types::<A, R>(&WasmHandlerApi::method_to_symbol(method))?;
+        let in_args = InArgs::try_new(method, data)?;
+        Instance::with(self.0.clone(), |ins| -> Result<R> {
+            ins.run_cancellable(handle, |ins| ins.handle_wasm(in_args)?.into())
+        })
+    }
     /// Carry the context to call the wasm specified method.
     pub fn ctx_call<C: Message, A: Message, R: Message>(
         &self,
@@ -58,11 +179,106 @@ impl WasmCaller {
         method: Method,
         data: A,
     ) -> Result<R> {
+        self.check_types::<A, R>(&WasmHandlerApi::method_to_symbol(method))?;
         let in_args = InArgs::try_new(method, data)?;
         Instance::with(self.0.clone(), |ins| -> Result<R> {
             ins.ctx_handle_wasm(ctx, in_args)?.into()
         })
     }
+    /// Call the wasm specified method whose payloads are packed with the codec,
+    /// e.g. `#[wasm_handle(1, codec = "json")]`, which fails with `CODE_PROTO`
+    /// if the codec is not in the `_wasmy_abi_codecs` exported by the wasm.
+    /// NOTE: Unlike `call`, the payload types are not checked against the
+    /// manifest of the wasm, which only records the protobuf message types.
+    pub fn call_with<C: Codec<A> + Codec<R>, A, R>(&self, method: Method, data: A) -> Result<R> {
+        let in_args = InArgs::try_new_with::<C, A>(method, data)?;
+        Instance::with(self.0.clone(), |ins| -> Result<R> {
+            ins.check_codec(<C as Codec<A>>::KIND)?;
+            ins.handle_wasm(in_args)?.into_result_with::<C, R>()
+        })
+    }
+    /// Call the wasm method exported by name, e.g. `#[wasm_handle(name = "user.create")]`.
+    pub fn call_named<A: Message, R: Message>(&self, name: &str, data: A) -> Result<R> {
+        self.check_types::<A, R>(&WasmHandlerApi::name_to_symbol(name))?;
+        let in_args = InArgs::try_new(method_id(name), data)?;
+        Instance::with(self.0.clone(), |ins| -> Result<R> {
+            ins.named_handle_wasm(None::<Empty>, name, in_args)?.into()
+        })
+    }
+    /// Carry the context to call the wasm method exported by name.
+    pub fn ctx_call_named<C: Message, A: Message, R: Message>(
+        &self,
+        ctx: C,
+        name: &str,
+        data: A,
+    ) -> Result<R> {
+        self.check_types::<A, R>(&WasmHandlerApi::name_to_symbol(name))?;
+        let in_args = InArgs::try_new(method_id(name), data)?;
+        Instance::with(self.0.clone(), |ins| -> Result<R> {
+            ins.named_handle_wasm(Some(ctx), name, in_args)?.into()
+        })
+    }
+    /// Call the wasm method of the protobuf enum variant, e.g.
+    /// `#[wasm_handle(MyMethods::Add)]`, whose value is the method.
+    pub fn call_enum<E: ProtobufEnum, A: Message, R: Message>(
+        &self,
+        method: E,
+        data: A,
+    ) -> Result<R> {
+        self.call(method.value(), data)
+    }
+    /// Carry the context to call the wasm method of the protobuf enum variant.
+    pub fn ctx_call_enum<C: Message, E: ProtobufEnum, A: Message, R: Message>(
+        &self,
+        ctx: C,
+        method: E,
+        data: A,
+    ) -> Result<R> {
+        self.ctx_call(ctx, method.value(), data)
+    }
+    /// Call the raw bytes handler of the method, e.g.
+    /// `#[wasm_raw_handle(1)]`, whose args and rets are written and read
+    /// right in the linear memory of the wasm.
+    pub fn call_raw_bytes(&self, method: Method, data: &[u8]) -> Result<Vec<u8>> {
+        self.call_raw_bytes_with(method, data, |rets| rets.to_vec())
+    }
+    /// Call the raw bytes handler of the method, with the rets borrowed from
+    /// the linear memory of the wasm, which saves copying them out.
+    pub fn call_raw_bytes_with<F, R>(&self, method: Method, data: &[u8], callback: F) -> Result<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let symbol = WasmHandlerApi::to_raw_symbol(&WasmHandlerApi::method_to_symbol(method));
+        Instance::with(self.0.clone(), |ins| -> Result<R> {
+            ins.raw_bytes_handle_wasm(&symbol, data, callback)
+        })
+    }
+    /// Call the raw bytes handler exported by name, e.g.
+    /// `#[wasm_raw_handle(name = "image.resize")]`.
+    pub fn call_raw_bytes_named(&self, name: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let symbol = WasmHandlerApi::to_raw_symbol(&WasmHandlerApi::name_to_symbol(name));
+        Instance::with(self.0.clone(), |ins| -> Result<Vec<u8>> {
+            ins.raw_bytes_handle_wasm(&symbol, data, |rets| rets.to_vec())
+        })
+    }
+    /// Call the stream handler of the method, e.g. `#[wasm_stream_handle(1)]`.
+    /// The wasm pulls the input chunks on demand, and each output chunk is
+    /// handed to the callback before the wasm goes on; the call fails with the
+    /// error of the callback once it refuses a chunk.
+    /// NOTE: The input and the output are owned by the call, so they borrow
+    /// nothing, e.g. the output chunks are collected through a channel.
+    pub fn call_stream<I, F>(&self, method: Method, input: I, output: F) -> Result<()>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+        F: FnMut(&[u8]) -> Result<()> + Send + 'static,
+    {
+        let symbol = WasmHandlerApi::to_stream_symbol(&WasmHandlerApi::method_to_symbol(method));
+        let input = Box::new(input.into_iter());
+        Instance::with(self.0.clone(), |ins| -> Result<()> {
+            ins.stream_handle_wasm(&symbol, input, Box::new(output))
+        })
+    }
     // // Execute the raw call to wasm.
     pub fn raw_call<B, A, R>(&self, sign_name: &str, do_args: B, do_rets: A) -> Result<R>
     where
@@ -78,6 +294,24 @@ impl WasmCaller {
             Ok(rets)
         })
     }
+    /// Get the instance pool metrics of the wasm.
+    pub fn pool_metrics(&self) -> Result<PoolMetrics> {
+        Ok(InstancePool::get_loaded(&self.0)?.metrics())
+    }
+    /// Get the compiled module cache statistics of the wasm.
+    pub fn module_cache_stats(&self) -> Result<ModuleCacheStats> {
+        Ok(InstancePool::get_loaded(&self.0)?.module_cache_stats())
+    }
+    /// Describe the interface of the wasm: the exported methods, the imports,
+    /// the memories and the custom sections.
+    pub fn describe(&self) -> Result<ModuleDescription> {
+        InstancePool::get_loaded(&self.0)?.describe()
+    }
+    /// Change the instance pool sizing of the wasm.
+    pub fn set_pool_config(&self, config: PoolConfig) -> Result<()> {
+        InstancePool::get_loaded(&self.0)?.set_config(config);
+        Ok(())
+    }
     /// Get instance and do custom operations.
     pub fn with<F, R>(&self, callback: F) -> Result<R>
     where
@@ -85,4 +319,57 @@ impl WasmCaller {
     {
         Instance::with(self.0.clone(), |ins| -> Result<R> { callback(ins) })
     }
+    /// Check the message types of the call against the manifest of the wasm.
+    fn check_types<A: Message, R: Message>(&self, symbol: &str) -> Result<()> {
+        InstancePool::get_loaded(&self.0)?.manifest().check::<A, R>(symbol)
+    }
+}
+
+#[cfg(feature = "async")]
+impl WasmCaller {
+    /// Call the wasm specified method on the blocking thread pool, without
+    /// blocking the async executor.
+    pub async fn call_async<A: Message, R: Message>(&self, method: Method, data: A) -> Result<R> {
+        let caller = self.clone();
+        spawn_blocking(move || caller.call(method, data)).await
+    }
+    /// Carry the context to call the wasm specified method on the blocking
+    /// thread pool, without blocking the async executor.
+    pub async fn ctx_call_async<C: Message, A: Message, R: Message>(
+        &self,
+        ctx: C,
+        method: Method,
+        data: A,
+    ) -> Result<R> {
+        let caller = self.clone();
+        spawn_blocking(move || caller.ctx_call(ctx, method, data)).await
+    }
+    /// Execute the raw call to wasm on the blocking thread pool, without
+    /// blocking the async executor.
+    pub async fn raw_call_async<B, A, R>(
+        &self,
+        sign_name: &str,
+        do_args: B,
+        do_rets: A,
+    ) -> Result<R>
+    where
+        B: FnOnce(&mut Context) -> Result<Box<[Value]>> + Send + 'static,
+        A: FnOnce(&Context, Box<[Value]>) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let caller = self.clone();
+        let sign_name = sign_name.to_string();
+        spawn_blocking(move || caller.raw_call(&sign_name, do_args, do_rets)).await
+    }
+}
+
+#[cfg(feature = "async")]
+async fn spawn_blocking<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(|| crate::VmHandlerApi::async_call(f))
+        .await
+        .map_err(|e| CodeMsg::new(CODE_RUNTIME, format!("join blocking task: {}", e)))?
 }
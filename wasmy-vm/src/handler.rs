@@ -1,29 +1,49 @@
 use std::{
-    any::Any as _,
     collections::HashMap,
-    sync::{Once, RwLock},
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex, RwLock},
 };
 
 pub use inventory::submit as submit_handler;
 use lazy_static::lazy_static;
-pub use wasmy_abi::{abi::*, types::*};
+pub use wasmy_abi::{abi::*, demangle_method_name, mangle_method_name, types::*};
 pub use wasmy_macros::vm_handle;
 
-pub type VmHandler = fn(usize, &Any) -> Result<Any>;
+use crate::{manifest::RequiredMethod, HostContext, HostEnv};
+
+pub type VmHandler = fn(&mut HostEnv, &Any) -> Result<Any>;
+/// A vm handler that can capture its environment, such as a closure.
+pub type DynVmHandler = Arc<dyn Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync>;
 
 pub struct VmHandlerApi {
     method: Method,
     handler: VmHandler,
+    name: &'static str,
+    set: &'static str,
+    method_name: &'static str,
 }
 
-static COLLECT_AND_REGISTER_ONCE: Once = Once::new();
+inventory::collect!(VmHandlerApi);
 
 impl VmHandlerApi {
     pub const fn new(method: Method, handler: VmHandler) -> Self {
-        VmHandlerApi { method, handler }
+        VmHandlerApi { method, handler, name: "", set: "", method_name: "" }
+    }
+    /// Set the name of the source function of the handler.
+    pub const fn with_name(self, name: &'static str) -> Self {
+        VmHandlerApi { name, ..self }
+    }
+    /// Put the handler into the named handler set instead of the global one.
+    pub const fn in_set(self, set: &'static str) -> Self {
+        VmHandlerApi { set, ..self }
+    }
+    /// Set the name of the named method, whose id is hashed from it.
+    pub const fn with_method_name(self, method_name: &'static str) -> Self {
+        VmHandlerApi { method_name, ..self }
     }
-    pub fn register(&self) {
-        set_handler(self.method, self.handler)
+    pub fn register(&self) -> Result<()> {
+        Self::collect_and_register_once()?;
+        MUX.write().unwrap().insert_api(self)
     }
     pub fn pack_any<R: Message>(data: R) -> Result<Any> {
         pack_any(data)
@@ -31,65 +51,428 @@ impl VmHandlerApi {
     pub fn unpack_any<R: Message>(data: &Any) -> Result<R> {
         unpack_any(data)
     }
-    pub unsafe fn try_as<T: Message>(ptr: usize) -> Option<&'static T> {
-        if ptr > 0 {
-            let ptr = ptr as *const T;
-            if !ptr.is_null() {
-                return Some(&*ptr);
-            }
+    /// Get the context value of the call, which is none if the call carries
+    /// no context, or a `CODE_PROTO` error if it is not of the expected type.
+    pub fn try_as<T: Message>(ctx: &HostContext) -> Result<Option<&T>> {
+        match ctx.get::<T>() {
+            Some(value) => Ok(Some(value)),
+            None if ctx.is_empty() => Ok(None),
+            None => CodeMsg::result(
+                CODE_PROTO,
+                format!(
+                    "context type mismatch: expected {}, got {:?}",
+                    std::any::type_name::<T>(),
+                    ctx.type_names()
+                ),
+            ),
+        }
+    }
+    /// Drive an async `#[vm_handle]` to completion on the current thread.
+    /// The tokio runtime of the caller is reused when there is one, which is a
+    /// multi-thread runtime, or a current-thread one only if the wasm is called
+    /// by `WasmCaller::call_async` and the like, as its only thread cannot
+    /// block on itself. Otherwise it fails with `CODE_RUNTIME`. Outside tokio, it
+    /// blocks on a current-thread runtime cached per thread.
+    #[cfg(feature = "async")]
+    pub fn block_on<R, F: std::future::Future<Output = Result<R>>>(future: F) -> Result<R> {
+        use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+        match Handle::try_current() {
+            Ok(handle) => match handle.runtime_flavor() {
+                RuntimeFlavor::CurrentThread if !IN_ASYNC_CALL.with(std::cell::Cell::get) => {
+                    CodeMsg::result(
+                        CODE_RUNTIME,
+                        "an async vm handler cannot block the current-thread runtime, call the \
+                         wasm by `call_async` or in a multi-thread runtime",
+                    )
+                }
+                RuntimeFlavor::CurrentThread => handle.block_on(future),
+                _ => tokio::task::block_in_place(|| handle.block_on(future)),
+            },
+            Err(_) => RUNTIME.with(|runtime| {
+                if runtime.get().is_none() {
+                    let _ = runtime.set(Builder::new_current_thread().enable_all().build()?);
+                }
+                runtime.get().unwrap().block_on(future)
+            }),
         }
-        None
     }
-    pub(crate) fn collect_and_register_once() {
-        COLLECT_AND_REGISTER_ONCE.call_once(|| collect_and_register_handlers());
+    /// Run the call of `WasmCaller::call_async` and the like on the blocking
+    /// thread, where an async `#[vm_handle]` may block on any runtime.
+    #[cfg(feature = "async")]
+    pub(crate) fn async_call<R>(call: impl FnOnce() -> R) -> R {
+        let outer = IN_ASYNC_CALL.with(|in_call| in_call.replace(true));
+        let ret = call();
+        IN_ASYNC_CALL.with(|in_call| in_call.set(outer));
+        ret
+    }
+    /// Register the collected global handlers once they all register, which
+    /// is retried by the next call if one of them fails.
+    pub(crate) fn collect_and_register_once() -> Result<()> {
+        let mut collected = COLLECTED.lock().unwrap();
+        if !*collected {
+            collect_and_register_handlers()?;
+            *collected = true;
+        }
+        Ok(())
+    }
+    /// Check that the vm methods that the wasm requires have handlers, failing
+    /// with `CODE_EXPORTS` that lists the undefined ones.
+    pub(crate) fn check_requires(
+        handlers: Option<&HandlerSet>,
+        requires: &[RequiredMethod],
+    ) -> Result<()> {
+        let mux = MUX.read().unwrap();
+        let handlers = handlers.unwrap_or(&mux);
+        let undefined: Vec<String> = requires
+            .iter()
+            .filter(|r| handlers.get(r.method).is_none())
+            .map(|r| {
+                if r.label.is_empty() {
+                    r.method.to_string()
+                } else {
+                    format!("{}({})", r.method, r.label)
+                }
+            })
+            .collect();
+        if undefined.is_empty() {
+            return Ok(());
+        }
+        CodeMsg::result(
+            CODE_EXPORTS,
+            format!(
+                "the wasm requires undefined virtual machine methods: {}",
+                undefined.join(", ")
+            ),
+        )
+    }
+    fn name(&self) -> &'static str {
+        if self.name.is_empty() { "<unnamed>" } else { self.name }
     }
 }
 
+#[cfg(feature = "async")]
+thread_local! {
+    /// Whether the thread runs a call of `WasmCaller::call_async`.
+    static IN_ASYNC_CALL: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    /// The current-thread runtime of the thread outside tokio, built by the
+    /// first async `#[vm_handle]` that it calls.
+    static RUNTIME: std::cell::OnceCell<tokio::runtime::Runtime> = const { std::cell::OnceCell::new() };
+}
+
 lazy_static! {
-    static ref MUX: RwLock<HashMap<Method, VmHandler>> =
-        RwLock::new(HashMap::<Method, VmHandler>::new());
+    static ref MUX: RwLock<HandlerSet> = RwLock::new(HandlerSet::new());
+    static ref COLLECTED: Mutex<bool> = Mutex::new(false);
 }
 
-fn collect_and_register_handlers() {
-    inventory::collect!(VmHandlerApi);
+/// Register the collected global handlers into a copy of the global ones, so
+/// none of them is registered if one fails.
+fn collect_and_register_handlers() -> Result<()> {
+    let mut mux = MUX.write().unwrap();
+    let mut handlers = mux.clone();
     for info in inventory::iter::<VmHandlerApi> {
-        info.register();
+        if info.set.is_empty() {
+            handlers.insert_api(info)?;
+        }
     }
-    for (method, hdl) in MUX.read().unwrap().iter() {
-        println!(
-            "collect_and_register_handlers: method={}, hdl_type_id={:?}",
-            method,
-            hdl.type_id()
-        );
+    #[cfg(debug_assertions)]
+    for info in handlers.list() {
+        println!("collect_and_register_handlers: method={}, name={}", info.method, info.name);
+    }
+    *mux = handlers;
+    Ok(())
+}
+
+/// Set the policy of registering a global handler for a method that already
+/// has one, which also applies to collecting the `#[vm_handle]` handlers if
+/// it is set before any of the global handlers is modified or any wasm is
+/// loaded.
+pub fn set_conflict_policy(policy: ConflictPolicy) {
+    MUX.write().unwrap().set_conflict_policy(policy)
+}
+
+/// Register the global handler of the method, which is a function or a
+/// closure, following the conflict policy.
+/// NOTE: Like the other functions of the global handlers, it collects the
+/// `#[vm_handle]` handlers first, so that loading a wasm later does not undo
+/// the change.
+#[track_caller]
+pub fn set_handler<H>(method: Method, hdl: H) -> Result<()>
+where
+    H: Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync + 'static,
+{
+    VmHandlerApi::collect_and_register_once()?;
+    MUX.write().unwrap().insert(method, hdl)
+}
+
+/// Replace the global handler of the method, which fails with `CODE_NONE` if
+/// there is none.
+#[track_caller]
+pub fn replace_handler<H>(method: Method, hdl: H) -> Result<HandlerInfo>
+where
+    H: Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync + 'static,
+{
+    VmHandlerApi::collect_and_register_once()?;
+    MUX.write().unwrap().replace(method, hdl)
+}
+
+/// Remove the global handler of the method.
+pub fn remove_handler(method: Method) -> Result<Option<HandlerInfo>> {
+    VmHandlerApi::collect_and_register_once()?;
+    Ok(MUX.write().unwrap().remove(method))
+}
+
+/// List the global handlers ordered by method.
+pub fn list_handlers() -> Result<Vec<HandlerInfo>> {
+    VmHandlerApi::collect_and_register_once()?;
+    Ok(MUX.read().unwrap().list())
+}
+
+/// Wrap the typed closure into a vm handler, like `#[vm_handle]` does for
+/// `fn(args: A) -> Result<R>`.
+pub fn handler_fn<A, R, F>(f: F) -> impl Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync
+where
+    A: Message,
+    R: Message,
+    F: Fn(A) -> Result<R> + Send + Sync + 'static,
+{
+    move |_env: &mut HostEnv, args: &Any| pack_any(f(unpack_any(args)?)?)
+}
+
+/// Wrap the typed closure into a vm handler, like `#[vm_handle]` does for
+/// `fn(ctx: Option<&C>, args: A) -> Result<R>`.
+pub fn ctx_handler_fn<C, A, R, F>(f: F) -> impl Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync
+where
+    C: Message,
+    A: Message,
+    R: Message,
+    F: Fn(Option<&C>, A) -> Result<R> + Send + Sync + 'static,
+{
+    move |env: &mut HostEnv, args: &Any| {
+        pack_any(f(VmHandlerApi::try_as(env.ctx)?, unpack_any(args)?)?)
+    }
+}
+
+/// Wrap the typed closure into a vm handler, like `#[vm_handle]` does for
+/// `fn(state: &mut S, args: A) -> Result<R>`.
+pub fn state_handler_fn<S, A, R, F>(
+    f: F,
+) -> impl Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync
+where
+    S: 'static,
+    A: Message,
+    R: Message,
+    F: Fn(&mut S, A) -> Result<R> + Send + Sync + 'static,
+{
+    move |env: &mut HostEnv, args: &Any| {
+        let args = unpack_any(args)?;
+        pack_any(f(env.state_mut::<S>()?, args)?)
     }
 }
 
-pub fn set_handler(method: Method, hdl: VmHandler) {
-    let ty = hdl.type_id();
-    if let Some(old) = MUX.write().unwrap().insert(method, hdl) {
-        if old.type_id() != ty {
-            panic!(
-                "duplicate register handler: method={}, old_type_id={:?}, new_type_id={:?}",
-                method,
-                old.type_id(),
-                ty
-            );
+/// What to do when registering a handler for a method that already has one.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Fail with `CODE_CONFLICT`.
+    Error,
+    /// Replace the registered handler, which is the default.
+    #[default]
+    Overwrite,
+    /// Keep the registered handler and ignore the new one.
+    KeepFirst,
+}
+
+/// A registered handler.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HandlerInfo {
+    pub method: Method,
+    /// The name of the source function of the handler, or the location that
+    /// a closure is registered at.
+    pub name: String,
+}
+
+#[derive(Clone)]
+struct HandlerEntry {
+    handler: DynVmHandler,
+    name: String,
+    /// The name of the named method, empty if unknown.
+    method_name: &'static str,
+}
+
+/// The name of the handler, which is the location of the caller for a closure,
+/// whose type name tells nothing of where it comes from.
+#[track_caller]
+fn handler_name<H>() -> String {
+    let name = std::any::type_name::<H>();
+    if name.contains("{{closure}}") {
+        let caller = std::panic::Location::caller();
+        format!("<closure at {}:{}>", caller.file(), caller.line())
+    } else {
+        name.to_string()
+    }
+}
+
+/// A set of vm handlers bound to the wasm modules loaded with it, instead of
+/// the global handlers.
+#[derive(Clone, Default)]
+pub struct HandlerSet {
+    handlers: HashMap<Method, HandlerEntry>,
+    policy: ConflictPolicy,
+}
+
+impl HandlerSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Collect the handlers registered by `#[vm_handle(method, set = "name")]`.
+    pub fn collect(name: &str) -> Result<Self> {
+        let mut set = Self::new();
+        for info in inventory::iter::<VmHandlerApi> {
+            if info.set == name {
+                set.insert_api(info)?;
+            }
+        }
+        Ok(set)
+    }
+    /// Set the policy of inserting a handler for a method that already has
+    /// one.
+    pub fn set_conflict_policy(&mut self, policy: ConflictPolicy) {
+        self.policy = policy;
+    }
+    /// Insert the handler following the conflict policy, like `insert`, and
+    /// return the set for chaining.
+    #[track_caller]
+    pub fn with<H>(mut self, method: Method, hdl: H) -> Result<Self>
+    where
+        H: Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync + 'static,
+    {
+        self.insert_named(method, hdl, handler_name::<H>(), "")?;
+        Ok(self)
+    }
+    /// Insert the handler, which is a function or a closure, following the
+    /// conflict policy.
+    #[track_caller]
+    pub fn insert<H>(&mut self, method: Method, hdl: H) -> Result<()>
+    where
+        H: Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync + 'static,
+    {
+        self.insert_named(method, hdl, handler_name::<H>(), "")
+    }
+    fn insert_api(&mut self, api: &VmHandlerApi) -> Result<()> {
+        self.insert_named(api.method, api.handler, api.name().to_string(), api.method_name)
+    }
+    /// Insert the handler following the conflict policy, but two named methods
+    /// whose names hash to the same id always fail with `CODE_CONFLICT`. A
+    /// handler of no method name keeps the one of the handler it overwrites.
+    fn insert_named<H>(
+        &mut self,
+        method: Method,
+        hdl: H,
+        name: String,
+        mut method_name: &'static str,
+    ) -> Result<()>
+    where
+        H: Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync + 'static,
+    {
+        if let Some(old) = self.handlers.get(&method) {
+            if !old.method_name.is_empty()
+                && !method_name.is_empty()
+                && old.method_name != method_name
+            {
+                return CodeMsg::result(
+                    CODE_CONFLICT,
+                    format!(
+                        "method name collision: {:?} of {} and {:?} of {} are both method {}",
+                        old.method_name, old.name, method_name, name, method
+                    ),
+                );
+            }
+            match self.policy {
+                ConflictPolicy::Error => {
+                    return CodeMsg::result(
+                        CODE_CONFLICT,
+                        format!(
+                            "duplicate register handler: method={}, old={}, new={}",
+                            method, old.name, name
+                        ),
+                    );
+                }
+                ConflictPolicy::KeepFirst => return Ok(()),
+                ConflictPolicy::Overwrite => {}
+            }
+            if method_name.is_empty() {
+                method_name = old.method_name;
+            }
         }
+        self.handlers.insert(method, HandlerEntry { handler: Arc::new(hdl), name, method_name });
+        Ok(())
+    }
+    /// Replace the handler of the method, which fails with `CODE_NONE` if
+    /// there is none, returning the replaced one. The method name is kept.
+    #[track_caller]
+    pub fn replace<H>(&mut self, method: Method, hdl: H) -> Result<HandlerInfo>
+    where
+        H: Fn(&mut HostEnv, &Any) -> Result<Any> + Send + Sync + 'static,
+    {
+        match self.handlers.get_mut(&method) {
+            Some(entry) => {
+                let name = handler_name::<H>();
+                let method_name = entry.method_name;
+                let new = HandlerEntry { handler: Arc::new(hdl), name, method_name };
+                let old = std::mem::replace(entry, new);
+                Ok(HandlerInfo { method, name: old.name })
+            }
+            None => CodeMsg::result(CODE_NONE, format!("no handler to replace: method={}", method)),
+        }
+    }
+    /// Remove the handler of the method.
+    pub fn remove(&mut self, method: Method) -> Option<HandlerInfo> {
+        self.handlers.remove(&method).map(|entry| HandlerInfo { method, name: entry.name })
+    }
+    pub fn get(&self, method: Method) -> Option<DynVmHandler> {
+        self.handlers.get(&method).map(|entry| entry.handler.clone())
+    }
+    /// List the handlers ordered by method.
+    pub fn list(&self) -> Vec<HandlerInfo> {
+        let mut list: Vec<HandlerInfo> = self
+            .handlers
+            .iter()
+            .map(|(method, entry)| HandlerInfo { method: *method, name: entry.name.clone() })
+            .collect();
+        list.sort_by_key(|info| info.method);
+        list
+    }
+    pub fn methods(&self) -> Vec<Method> {
+        self.list().into_iter().map(|info| info.method).collect()
+    }
+}
+
+impl Debug for HandlerSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandlerSet").field("handlers", &self.list()).finish()
     }
 }
 
 #[allow(dead_code)]
-pub(crate) fn vm_invoke(ctx_ptr: usize, args_pb: &Vec<u8>) -> OutRets {
-    match InArgs::parse_from_bytes(&args_pb) {
-        Ok(vm_args) => handle(ctx_ptr, vm_args),
+pub(crate) fn vm_invoke(
+    handlers: Option<&HandlerSet>,
+    env: &mut HostEnv,
+    args_pb: &[u8],
+) -> OutRets {
+    match InArgs::parse_from_bytes(args_pb) {
+        Ok(vm_args) => handle(handlers, env, vm_args),
         Err(err) => CodeMsg::new(CODE_PROTO, err).into(),
     }
 }
 
-fn handle(ctx_ptr: usize, args: InArgs) -> OutRets {
-    let res: Result<Any> = MUX.read().unwrap().get(&args.get_method()).ok_or_else(|| {
-        CodeMsg::new(CODE_NONE, format!("undefined virtual machine method({})", args.get_method()))
-    })?(ctx_ptr, args.get_data());
+fn handle(handlers: Option<&HandlerSet>, env: &mut HostEnv, mut args: InArgs) -> OutRets {
+    let method = args.get_method();
+    let hdl = match handlers {
+        Some(set) => set.get(method),
+        None => MUX.read().unwrap().get(method),
+    };
+    let res: Result<Any> = hdl.ok_or_else(|| {
+        CodeMsg::new(CODE_NONE, format!("undefined virtual machine method({})", method))
+    })?(env, &args.take_packed());
     match res {
         Ok(a) => a.into(),
         Err(e) => e.into(),
@@ -102,17 +485,79 @@ impl WasmHandlerApi {
     pub(crate) const fn onload_symbol() -> &'static str {
         "_wasmy_wasm_onload"
     }
+    pub(crate) const fn abi_version_symbol() -> &'static str {
+        "_wasmy_abi_version"
+    }
+    pub(crate) const fn codecs_symbol() -> &'static str {
+        "_wasmy_abi_codecs"
+    }
+    pub(crate) const fn alloc_symbol() -> &'static str {
+        "_wasmy_alloc"
+    }
+    pub(crate) const fn dealloc_symbol() -> &'static str {
+        "_wasmy_dealloc"
+    }
+    pub(crate) const fn manifest_symbol() -> &'static str {
+        "_wasmy_manifest"
+    }
     pub(crate) fn method_to_symbol(method: WasmMethod) -> String {
         format!("_wasmy_wasm_handle_{}", method)
     }
     pub(crate) fn symbol_to_method(symbol: &str) -> Option<WasmMethod> {
         if let Some(s) = symbol.strip_prefix("_wasmy_wasm_handle_") { s.parse().ok() } else { None }
     }
+    pub(crate) fn name_to_symbol(name: &str) -> String {
+        format!("_wasmy_wasm_named_{}", mangle_method_name(name))
+    }
+    pub(crate) fn symbol_to_name(symbol: &str) -> Option<String> {
+        demangle_method_name(symbol.strip_prefix("_wasmy_wasm_named_")?)
+    }
+    /// The symbol of the raw bytes handler of the method symbol, e.g.
+    /// `_wasmy_wasm_raw_handle_10` of `_wasmy_wasm_handle_10`.
+    pub(crate) fn to_raw_symbol(symbol: &str) -> String {
+        Self::to_kind_symbol("raw", symbol)
+    }
+    /// The method symbol of the raw bytes handler symbol.
+    pub(crate) fn from_raw_symbol(symbol: &str) -> Option<String> {
+        Self::from_kind_symbol("raw", symbol)
+    }
+    /// The symbol of the stream handler of the method symbol, e.g.
+    /// `_wasmy_wasm_stream_handle_10` of `_wasmy_wasm_handle_10`.
+    pub(crate) fn to_stream_symbol(symbol: &str) -> String {
+        Self::to_kind_symbol("stream", symbol)
+    }
+    /// The method symbol of the stream handler symbol.
+    pub(crate) fn from_stream_symbol(symbol: &str) -> Option<String> {
+        Self::from_kind_symbol("stream", symbol)
+    }
+    fn to_kind_symbol(kind: &str, symbol: &str) -> String {
+        format!("_wasmy_wasm_{}{}", kind, symbol.strip_prefix("_wasmy_wasm").unwrap_or(symbol))
+    }
+    fn from_kind_symbol(kind: &str, symbol: &str) -> Option<String> {
+        let s = symbol.strip_prefix("_wasmy_wasm_")?.strip_prefix(kind)?;
+        s.starts_with('_').then(|| format!("_wasmy_wasm{}", s))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::WasmHandlerApi;
+    use crate::{
+        handler::vm_invoke, handler_fn, list_handlers, load_wasm, method_id, pack_with,
+        remove_handler, set_handler, unpack_with, Any, ConflictPolicy, Empty, HandlerSet,
+        HostContext, HostEnv, InArgs, Message, RawCodec, Result, VmHandlerApi, WasmHandlerApi,
+        CODE_CONFLICT, CODE_PROTO,
+    };
+
+    fn collected(_env: &mut HostEnv, args: &Any) -> Result<Any> {
+        Ok(args.clone())
+    }
+
+    inventory::submit! {
+        VmHandlerApi::new(9101, collected).with_name("collected")
+    }
+    inventory::submit! {
+        VmHandlerApi::new(9102, collected).with_name("collected")
+    }
 
     #[test]
     fn method_to_symbol() {
@@ -125,4 +570,158 @@ mod tests {
         let method = WasmHandlerApi::symbol_to_method("_wasmy_wasm_handle_10");
         assert_eq!(method, Some(10));
     }
+
+    #[test]
+    fn named_symbol() {
+        let symbol = WasmHandlerApi::name_to_symbol("user.create");
+        assert_eq!(WasmHandlerApi::symbol_to_name(&symbol).as_deref(), Some("user.create"));
+        assert_eq!(WasmHandlerApi::symbol_to_name("_wasmy_wasm_handle_10"), None);
+        assert!(method_id("user.create") < 0);
+        assert_ne!(method_id("user.create"), method_id("user.delete"));
+    }
+
+    #[test]
+    fn kind_symbol() {
+        let symbol = WasmHandlerApi::to_raw_symbol("_wasmy_wasm_handle_10");
+        assert_eq!(symbol, "_wasmy_wasm_raw_handle_10");
+        assert_eq!(
+            WasmHandlerApi::from_raw_symbol(&symbol).as_deref(),
+            Some("_wasmy_wasm_handle_10")
+        );
+        assert_eq!(WasmHandlerApi::from_raw_symbol("_wasmy_wasm_handle_10"), None);
+        let symbol = WasmHandlerApi::to_stream_symbol("_wasmy_wasm_named_a");
+        assert_eq!(symbol, "_wasmy_wasm_stream_named_a");
+        assert_eq!(
+            WasmHandlerApi::from_stream_symbol(&symbol).as_deref(),
+            Some("_wasmy_wasm_named_a")
+        );
+    }
+
+    #[test]
+    fn codec_payload() {
+        let mut set = HandlerSet::new();
+        set.insert(1, |_env: &mut HostEnv, args: &Any| {
+            let mut args: Vec<u8> = unpack_with::<RawCodec, _>(args)?;
+            args.reverse();
+            pack_with::<RawCodec, _>(args)
+        })
+        .unwrap();
+        // the bytes of the codec are framed as they are, without an `Any`
+        let args = InArgs::try_new_with::<RawCodec, _>(1, vec![1, 2, 3]).unwrap();
+        assert!(!args.has_data());
+        assert_eq!(args.get_payload(), [1, 2, 3]);
+        let (ctx, mut call, mut instance) = Default::default();
+        let mut env = HostEnv { ctx: &ctx, call: &mut call, instance: &mut instance };
+        let rets = vm_invoke(Some(&set), &mut env, &args.write_to_bytes().unwrap());
+        assert!(!rets.has_data());
+        assert_eq!(rets.into_result_with::<RawCodec, Vec<u8>>().unwrap(), vec![3, 2, 1]);
+        let args = InArgs::try_new(1, Empty::new()).unwrap();
+        let rets = vm_invoke(Some(&set), &mut env, &args.write_to_bytes().unwrap());
+        assert_eq!(rets.get_code(), CODE_PROTO);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn block_on() {
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let err = rt.block_on(async { VmHandlerApi::block_on(async { Ok(1) }) }).unwrap_err();
+        assert_eq!(err.code, crate::CODE_RUNTIME);
+        // a call of `call_async` runs on a blocking thread, which may block
+        let call = || VmHandlerApi::async_call(|| VmHandlerApi::block_on(async { Ok(1) }));
+        let rets = rt.block_on(async { tokio::task::spawn_blocking(call).await.unwrap() });
+        assert_eq!(rets.unwrap(), 1);
+        assert_eq!(VmHandlerApi::block_on(async { Ok(2) }).unwrap(), 2);
+        // the runtime outside tokio is built once per thread
+        let id = || VmHandlerApi::block_on(async { Ok(tokio::runtime::Handle::current().id()) });
+        assert_eq!(id().unwrap(), id().unwrap());
+    }
+
+    #[test]
+    fn try_as() {
+        let mut ctx = HostContext::default();
+        assert!(VmHandlerApi::try_as::<Empty>(&ctx).unwrap().is_none());
+        ctx.insert(Empty::new());
+        assert!(VmHandlerApi::try_as::<Empty>(&ctx).unwrap().is_some());
+        assert_eq!(VmHandlerApi::try_as::<InArgs>(&ctx).unwrap_err().code, CODE_PROTO);
+    }
+
+    #[test]
+    fn handler_set() {
+        fn echo(_env: &mut HostEnv, args: &Any) -> Result<Any> {
+            Ok(args.clone())
+        }
+        let mut set = HandlerSet::new()
+            .with(1, echo)
+            .and_then(|set| set.with(2, handler_fn(|args: Empty| Ok(args))))
+            .unwrap();
+        assert!(set.get(1).is_some());
+        assert!(set.get(2).is_some());
+        assert!(set.get(3).is_none());
+        // the chained handlers follow the conflict policy too
+        set.set_conflict_policy(ConflictPolicy::Error);
+        assert_eq!(set.with(1, echo).err().map(|err| err.code), Some(CODE_CONFLICT));
+    }
+
+    #[test]
+    fn method_name_collision() {
+        let api = |name| VmHandlerApi::new(7, collected).with_method_name(name);
+        let mut set = HandlerSet::new();
+        set.insert_api(&api("kv.get")).unwrap();
+        // the same method is overwritten by the policy
+        set.insert_api(&api("kv.get")).unwrap();
+        // another method of the same id fails whatever the policy is
+        let err = set.insert_api(&api("kv.set")).unwrap_err();
+        assert_eq!(err.code, CODE_CONFLICT);
+        set.set_conflict_policy(ConflictPolicy::KeepFirst);
+        assert_eq!(set.insert_api(&api("kv.set")).unwrap_err().code, CODE_CONFLICT);
+        // the handlers that replace or overwrite it keep the method name
+        set.replace(7, collected).unwrap();
+        assert_eq!(set.insert_api(&api("kv.set")).unwrap_err().code, CODE_CONFLICT);
+        set.set_conflict_policy(ConflictPolicy::Overwrite);
+        set.insert(7, collected).unwrap();
+        assert_eq!(set.insert_api(&api("kv.set")).unwrap_err().code, CODE_CONFLICT);
+        set.insert_api(&api("kv.get")).unwrap();
+    }
+
+    #[test]
+    fn conflict_policy() {
+        fn echo(_env: &mut HostEnv, args: &Any) -> Result<Any> {
+            Ok(args.clone())
+        }
+        let mut set = HandlerSet::new();
+        set.insert(1, handler_fn(|args: Empty| Ok(args))).unwrap();
+        set.insert(1, echo).unwrap();
+        assert!(set.list()[0].name.ends_with("echo"));
+        set.set_conflict_policy(ConflictPolicy::Error);
+        assert_eq!(set.insert(1, echo).unwrap_err().code, CODE_CONFLICT);
+        set.set_conflict_policy(ConflictPolicy::KeepFirst);
+        set.insert(1, handler_fn(|args: Empty| Ok(args))).unwrap();
+        assert!(set.list()[0].name.ends_with("echo"));
+        assert!(set.replace(1, handler_fn(|args: Empty| Ok(args))).unwrap().name.ends_with("echo"));
+        // a closure is named by where it is registered
+        let name = set.list()[0].name.clone();
+        assert!(name.contains(file!()), "{}", name);
+        assert!(set.replace(2, echo).is_err());
+        assert_eq!(set.remove(1).unwrap().method, 1);
+        assert!(set.list().is_empty());
+    }
+
+    #[test]
+    fn collect_before_mutation() {
+        // the `#[vm_handle]` handlers are collected before the global handlers
+        // are modified, so the load does not undo the change
+        assert_eq!(remove_handler(9101).unwrap().unwrap().name, "collected");
+        set_handler(9102, handler_fn(|args: Empty| Ok(args))).unwrap();
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+                (memory (export "memory") 1))"#,
+        )
+        .unwrap();
+        load_wasm(("collect_before_mutation_test", wasm)).unwrap();
+        let handlers = list_handlers().unwrap();
+        assert!(handlers.iter().all(|info| info.method != 9101));
+        let info = handlers.iter().find(|info| info.method == 9102).unwrap();
+        assert!(info.name.contains(file!()), "{}", info.name);
+    }
 }
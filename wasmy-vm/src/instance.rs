@@ -2,23 +2,46 @@ use core::ops::FnOnce;
 use std::{
     cell::{RefCell, RefMut},
     collections::HashMap,
-    sync::{Mutex, RwLock},
-    thread,
-    thread::ThreadId,
+    mem,
+    sync::{Arc, Mutex},
 };
 
-use lazy_static;
-use wasmer::{Exports, Function, Imports, MemoryView, Module, Store, Type, Value};
+use wasmer::{
+    AsStoreMut, Exports, Function, Imports, Memory, MemoryView, Module, RuntimeError, Store, Type,
+    Value,
+};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 use wasmer_wasi::{WasiFunctionEnv, WasiState, WasiStateBuilder};
+use wasmy_abi::CodecKind;
 
 use crate::{
-    context, context::Context, handler::*, instance_env::InstanceEnv, wasm_file,
-    wasm_file::WasmFile, WasmUri,
+    context,
+    context::{Context, HostContext, HostEnv},
+    handler::*,
+    instance_env::InstanceEnv,
+    interrupt::{check_interrupt, CancelHandle, INTERRUPT_IMPORT},
+    limits::GrowFailure,
+    manifest::Manifest,
+    module::{CompiledModule, MeteringConfig},
+    pool::InstancePool,
+    stream::{StreamInput, StreamOutput, StreamScope, StreamState},
+    wasm_file,
+    wasm_file::WasmFile,
+    LoadOptions, WasmUri,
 };
 
+/// The ABI version that the raw bytes handlers and the guest allocator come with.
+const RAW_BYTES_ABI_VERSION: AbiVersion = AbiVersion { major: 1, minor: 2 };
+/// The ABI version since which the vm places the args and the rets of
+/// `_wasmy_vm_call` into the wasm with the guest allocator.
+const HOST_ALLOC_ABI_VERSION: AbiVersion = AbiVersion { major: 1, minor: 3 };
+/// The ABI version that the stream handlers and the stream imports come with.
+const STREAM_ABI_VERSION: AbiVersion = AbiVersion { major: 1, minor: 4 };
+
 pub type FunctionEnvMut<'a> = wasmer::FunctionEnvMut<'a, InstanceEnv>;
 pub type FunctionEnv = wasmer::FunctionEnv<InstanceEnv>;
 pub type FnCheckModule = fn(&Module) -> Result<()>;
+pub type FnInitState = fn(wasm_uri: &WasmUri, state: &mut HostContext) -> Result<()>;
 pub type FnBuildImports = fn(
     builder: &mut WasiStateBuilder,
     store: &mut Store,
@@ -26,28 +49,27 @@ pub type FnBuildImports = fn(
     env: &FunctionEnv,
 ) -> Result<(WasiFunctionEnv, Imports)>;
 
-lazy_static::lazy_static! {
-    pub(crate) static ref INSTANCES: RwLock<HashMap<LocalInstanceKey, Mutex<Box<Instance>>>> = RwLock::new(HashMap::new());
-}
-
 #[derive(Debug)]
 pub struct Instance {
-    key: LocalInstanceKey,
+    key: InstanceKey,
     instance: wasmer::Instance,
     store: Store,
     context: RefCell<Context>,
+    env: FunctionEnv,
+    manifest: Arc<Manifest>,
+    metering: Option<MeteringConfig>,
+    interruptible: bool,
+    grow_failure: GrowFailure,
+    handlers: Option<Arc<HandlerSet>>,
+    abi_version: AbiVersion,
+    codecs: i32,
+    poisoned: bool,
 }
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
-pub struct LocalInstanceKey {
+pub struct InstanceKey {
     pub(crate) wasm_uri: WasmUri,
-    pub(crate) thread_id: ThreadId,
-}
-
-impl LocalInstanceKey {
-    pub(crate) fn from(wasm_uri: WasmUri) -> LocalInstanceKey {
-        LocalInstanceKey { wasm_uri, thread_id: thread::current().id() }
-    }
+    pub(crate) id: usize,
 }
 
 unsafe impl Sync for Instance {}
@@ -55,77 +77,94 @@ unsafe impl Sync for Instance {}
 unsafe impl Send for Instance {}
 
 impl Instance {
-    pub fn thread_id(&self) -> &ThreadId {
-        &self.key.thread_id
+    /// The sequence number of the instance in the pool of its module.
+    pub fn id(&self) -> usize {
+        self.key.id
     }
     pub fn wasm_uri(&self) -> &WasmUri {
         &self.key.wasm_uri
     }
-    pub(crate) fn install<B, W>(
-        wasm_file: W,
-        check_module: Option<FnCheckModule>,
-        build_imports: Option<FnBuildImports>,
-    ) -> Result<WasmUri>
+    /// Compile the wasm file and create its instance pool. A previously
+    /// loaded version of the same URI is replaced after its in-flight calls
+    /// are drained.
+    pub(crate) fn install<B, W>(wasm_file: W, options: LoadOptions) -> Result<WasmUri>
     where
         B: AsRef<[u8]>,
         W: WasmFile<B>,
     {
         // collect and register handlers once
-        VmHandlerApi::collect_and_register_once();
-        // read and cache wasm file
-        let wasm_uri = wasm_file::register_file(wasm_file)?;
-        Self::create_local(
-            wasm_uri.clone(),
-            wasm_file::get_files().get(&wasm_uri).unwrap(),
-            check_module,
-            build_imports,
-            true,
-        )?;
+        VmHandlerApi::collect_and_register_once()?;
+        // read wasm file, and cache it only after it is proven to work
+        let (wasm_uri, wasm_bytes) = wasm_file::read_file(wasm_file)?;
+        let mut compiled = CompiledModule::compile(&wasm_uri, &wasm_bytes, &options)?;
+        VmHandlerApi::check_requires(options.handlers.as_deref(), compiled.manifest.requires())?;
+        let first = Self::create_local(wasm_uri.clone(), 0, &compiled, &options, true)?;
+        compiled.manifest = first.manifest.clone();
+        let old = InstancePool::install(first, compiled, options)?;
+        wasm_file::insert_file(wasm_uri.clone(), wasm_bytes);
+        if let Some(old) = old {
+            old.drain();
+            #[cfg(debug_assertions)]
+            println!("reloaded wasm, uri={}", wasm_uri);
+            return Ok(wasm_uri);
+        }
         #[cfg(debug_assertions)]
         println!("loaded wasm, uri={}", wasm_uri);
         Ok(wasm_uri)
     }
 
-    fn create_local(
-        wasm_uri: WasmUri,
-        wasm_bytes: &Vec<u8>,
-        check_module: Option<FnCheckModule>,
-        build_imports: Option<FnBuildImports>,
-        first: bool,
-    ) -> Result<()> {
-        #[cfg(debug_assertions)]
-        println!("compiling module, wasm_uri={}...", wasm_uri);
+    /// Reload the wasm file with the options its previous version was loaded
+    /// with.
+    pub(crate) fn reinstall<B, W>(wasm_file: W) -> Result<WasmUri>
+    where
+        B: AsRef<[u8]>,
+        W: WasmFile<B>,
+    {
+        let (wasm_uri, wasm_bytes) = wasm_file::read_file(wasm_file)?;
+        let options =
+            InstancePool::get(&wasm_uri).map(|pool| pool.options().clone()).unwrap_or_default();
+        Self::install((wasm_uri.as_str(), wasm_bytes), options)
+    }
 
-        let engine;
-        #[cfg(not(feature = "llvm"))]
-        {
-            engine = wasmer_compiler_cranelift::Cranelift::default();
-            #[cfg(debug_assertions)]
-            if first {
-                println!("======== wasmy cranelift feature ========")
-            }
-        }
-        #[cfg(feature = "llvm")]
-        {
-            engine = wasmer_compiler_llvm::LLVM::default();
-            #[cfg(debug_assertions)]
-            if first {
-                println!("======== wasmy llvm feature ========")
+    /// Drain the in-flight calls, then drop all the instances and the file.
+    pub(crate) fn uninstall(wasm_uri: &WasmUri) -> Result<()> {
+        let file = wasm_file::unregister_file(wasm_uri);
+        match InstancePool::uninstall(wasm_uri) {
+            Some(pool) => pool.drain(),
+            None if file.is_none() => {
+                return CodeMsg::result(
+                    CODE_NONE,
+                    format!("wasm file not found, wasm_uri={}", wasm_uri),
+                );
             }
+            None => {}
         }
+        #[cfg(debug_assertions)]
+        println!("unloaded wasm, uri={}", wasm_uri);
+        Ok(())
+    }
 
-        let mut store: Store = Store::new(engine);
-        let mut module = Module::from_binary(&store, wasm_bytes)?;
-        module.set_name(wasm_uri.as_str());
-        if let Some(cf) = check_module {
-            cf(&module)?;
-        };
+    pub(crate) fn create_local(
+        wasm_uri: WasmUri,
+        id: usize,
+        compiled: &CompiledModule,
+        options: &LoadOptions,
+        first: bool,
+    ) -> Result<Box<Instance>> {
+        let (mut store, grow_failure) = options.limits.new_store(compiled.engine.clone());
+        let mut module = compiled.module.clone();
+        // the params of the handlers, checked against the ABI version of the wasm
+        let mut handle_params = vec![];
         if first {
+            if let Some(cf) = options.check_module {
+                cf(&module)?;
+            };
+            let mut methods = HashMap::new();
             for function in module.exports().functions() {
                 let name = function.name();
                 if name == WasmHandlerApi::onload_symbol() {
                     let ty = function.ty();
-                    if ty.params().len() > 0 || ty.results().len() > 0 {
+                    if !ty.params().is_empty() || !ty.results().is_empty() {
                         return CodeMsg::result(
                             CODE_EXPORTS,
                             format!(
@@ -136,34 +175,110 @@ impl Instance {
                     }
                     continue;
                 }
-                WasmHandlerApi::symbol_to_method(name).map_or_else(
+                if name == WasmHandlerApi::abi_version_symbol()
+                    || name == WasmHandlerApi::codecs_symbol()
+                {
+                    let ty = function.ty();
+                    if !ty.params().is_empty() || ty.results() != [Type::I32] {
+                        return CodeMsg::result(
+                            CODE_EXPORTS,
+                            format!("Incompatible Export Type: fn {}() -> i32 {{}}", name),
+                        );
+                    }
+                    continue;
+                }
+                if name == WasmHandlerApi::alloc_symbol()
+                    || name == WasmHandlerApi::dealloc_symbol()
+                {
+                    let ty = function.ty();
+                    let expected: (&[Type], &[Type]) = if name == WasmHandlerApi::alloc_symbol() {
+                        (&[Type::I32], &[Type::I32])
+                    } else {
+                        (&[Type::I32, Type::I32], &[])
+                    };
+                    if (ty.params(), ty.results()) != expected {
+                        return CodeMsg::result(
+                            CODE_EXPORTS,
+                            format!("Incompatible Export Type: {:?}", function),
+                        );
+                    }
+                    continue;
+                }
+                if name == WasmHandlerApi::manifest_symbol() {
+                    let ty = function.ty();
+                    if !ty.params().is_empty() || ty.results() != [Type::I64] {
+                        return CodeMsg::result(
+                            CODE_EXPORTS,
+                            format!("Incompatible Export Type: fn {}() -> i64 {{}}", name),
+                        );
+                    }
+                    continue;
+                }
+                // the raw bytes and the stream handlers have their own methods
+                let (kind, symbol) = match (
+                    WasmHandlerApi::from_raw_symbol(name),
+                    WasmHandlerApi::from_stream_symbol(name),
+                ) {
+                    (Some(symbol), _) => ("raw", symbol),
+                    (_, Some(symbol)) => ("stream", symbol),
+                    _ => ("", name.to_string()),
+                };
+                let method = match WasmHandlerApi::symbol_to_name(&symbol) {
+                    Some(method_name) => Some((method_id(&method_name), method_name)),
+                    None => WasmHandlerApi::symbol_to_method(&symbol)
+                        .or_else(|| compiled.manifest.enum_method(name))
+                        .map(|m| (m, m.to_string())),
+                };
+                if let Some((method, method_name)) = &method {
+                    if let Some(other) = methods.insert((kind, *method), method_name.clone()) {
+                        return CodeMsg::result(
+                            CODE_EXPORTS,
+                            format!(
+                                "duplicate wasm method: {:?} has the same method id {} as {:?}",
+                                method_name, method, other
+                            ),
+                        );
+                    }
+                }
+                method.map_or_else(
                     || {
                         #[cfg(debug_assertions)]
-                        {
-                            println!("module exports non-wasmy function: {:?}", function);
-                            Ok(())
-                        }
+                        println!("module exports non-wasmy function: {:?}", function);
+                        Ok(())
                     },
                     |_method| {
                         let ty = function.ty();
-                        if ty.results().len() == 0 && ty.params().eq(&[Type::I32, Type::I32]) {
+                        let valid = match kind {
+                            "raw" => {
+                                ty.params() == [Type::I32, Type::I32] && ty.results() == [Type::I64]
+                            }
+                            "stream" => ty.params() == [Type::I32] && ty.results().is_empty(),
+                            _ => {
+                                handle_params.push((name.to_string(), ty.params().len()));
+                                matches!(
+                                    ty.params(),
+                                    [Type::I32, Type::I32] | [Type::I32, Type::I32, Type::I32]
+                                ) && ty.results().is_empty()
+                            }
+                        };
+                        if valid {
                             #[cfg(debug_assertions)]
                             println!("module exports wasmy function: {:?}", function);
                             Ok(())
                         } else {
-                            return CodeMsg::result(
+                            CodeMsg::result(
                                 CODE_EXPORTS,
                                 format!("Incompatible Export Type: {:?}", function),
-                            );
+                            )
                         }
                     },
                 )?;
             }
         }
-        let key = LocalInstanceKey::from(wasm_uri);
+        let key = InstanceKey { wasm_uri, id };
         let ins_env = FunctionEnv::new(&mut store, InstanceEnv::default());
         let (wasi_env, imports) =
-            Self::build_imports(&key, &mut module, &mut store, &ins_env, build_imports)?;
+            Self::build_imports(&key, &mut module, &mut store, &ins_env, options.build_imports)?;
         #[cfg(debug_assertions)]
         if first {
             for ((namespace, name), r#extern) in imports.clone().into_iter() {
@@ -173,47 +288,94 @@ impl Instance {
                 );
             }
         }
+        let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
         let mut instance = Instance {
             key,
-            instance: wasmer::Instance::new(&mut store, &module, &imports)?,
+            instance,
             store,
             context: RefCell::new(Context::with_capacity(1024)),
+            env: ins_env,
+            manifest: compiled.manifest.clone(),
+            metering: options.metering.clone(),
+            interruptible: options.interruptible,
+            grow_failure,
+            handlers: options.handlers.clone(),
+            abi_version: AbiVersion::UNVERSIONED,
+            codecs: CodecKind::Protobuf.bit(),
+            poisoned: false,
         };
+        if let Some(version) = instance.read_i32_export(WasmHandlerApi::abi_version_symbol())? {
+            instance.abi_version = AbiVersion::from_i32(version);
+        }
+        if !ABI_VERSION.supports(instance.abi_version) {
+            return CodeMsg::result(
+                CODE_VERSION,
+                format!(
+                    "incompatible ABI version: the wasm is built with {}, but the vm supports {}",
+                    instance.abi_version, ABI_VERSION
+                ),
+            );
+        }
+        // the wasm of ABI 1.3 also passes the offset of the args
+        let params = if instance.abi_version < HOST_ALLOC_ABI_VERSION { 2 } else { 3 };
+        if let Some((name, _)) = handle_params.iter().find(|(_, len)| *len != params) {
+            return CodeMsg::result(
+                CODE_EXPORTS,
+                format!(
+                    "Incompatible Export Type: {} of the wasm of ABI {} takes {} params",
+                    name, instance.abi_version, params
+                ),
+            );
+        }
+        // the wasm of ABI 1.0 only speaks protobuf
+        if let Some(codecs) = instance.read_i32_export(WasmHandlerApi::codecs_symbol())? {
+            instance.codecs = codecs & CodecKind::supported();
+        }
 
         // Attach the memory export
         let memory = instance.instance.exports.get_memory("memory").unwrap();
         wasi_env.data_mut(&mut instance.store).set_memory(memory.clone());
 
-        // initialize
-        instance.into_init(ins_env)?;
+        // the message types are the same for all the instances
+        if first {
+            instance.read_types()?;
+        }
+
+        // the host state is ready before the wasm initialization
+        if let Some(init_state) = options.init_state {
+            init_state(&instance.key.wasm_uri, &mut instance.context.get_mut().state)?;
+        }
 
-        return Ok(());
+        // initialize
+        instance.into_init()
     }
 
+    /// Run the callback with an instance checked out from the pool of the
+    /// module. A poisoned instance is dropped when it is returned, so the next
+    /// call gets a fresh one.
     pub(crate) fn with<F, R>(wasm_uri: WasmUri, callback: F) -> Result<R>
     where
         F: FnOnce(&mut Instance) -> Result<R>,
     {
-        let key = LocalInstanceKey::from(wasm_uri.clone());
-        {
-            if let Some(ins) = INSTANCES.read().unwrap().get(&key) {
-                return callback(ins.lock().unwrap().as_mut());
+        loop {
+            if let Some(mut ins) = InstancePool::get_loaded(&wasm_uri)?.checkout()? {
+                // every call starts with the default budget
+                if let Some(metering) = &ins.metering {
+                    let budget = metering.default_budget;
+                    ins.set_fuel(budget)?;
+                }
+                let ret = callback(&mut ins);
+                ins.mut_context().host.clear();
+                // a failed call leaves its context behind
+                ins.mut_context().reverted();
+                return ret;
             }
+            // the pool was closed by reloading or unloading, look it up again
         }
-        let files = wasm_file::get_files();
-        let wasm_bytes = files.get(&key.wasm_uri);
-        if wasm_bytes.is_none() {
-            return Err(CodeMsg::new(
-                CODE_WASI,
-                format!("wasm file not found, wasm_uri={}", key.wasm_uri),
-            ));
-        }
-        Self::create_local(key.wasm_uri.clone(), wasm_bytes.unwrap(), None, None, false)?;
-        Self::with(wasm_uri, callback)
     }
 
     fn build_imports(
-        key: &LocalInstanceKey,
+        key: &InstanceKey,
         module: &mut Module,
         store: &mut Store,
         ins_env: &FunctionEnv,
@@ -226,24 +388,24 @@ impl Instance {
             ins_env,
         )?;
         let mut env_namespace =
-            imports.get_namespace_exports("env").unwrap_or_else(|| Exports::new());
+            imports.get_namespace_exports("env").unwrap_or_default();
         env_namespace.insert(
             "_wasmy_vm_recall",
             Function::new_typed_with_env(
                 store,
                 ins_env,
                 |ins_env: FunctionEnvMut, is_ctx: i32, offset: i32| {
-                    let ins_env = ins_env.data();
-                    let key = &ins_env.key;
+                    let ins = ins_env.data();
+                    let key = &ins.key;
                     #[cfg(debug_assertions)]
                     println!(
-                        "[VM:{:?}]_wasmy_vm_recall: wasm_uri={}, is_ctx={}, offset={}",
-                        key.thread_id,
+                        "[VM:{}]_wasmy_vm_recall: wasm_uri={}, is_ctx={}, offset={}",
+                        key.id,
                         key.wasm_uri,
                         is_ctx != 0,
                         offset
                     );
-                    ins_env.ctx_write_to(is_ctx != 0, offset as u64);
+                    ins.ctx_write_to(&ins.memory().view(&ins_env), is_ctx != 0, offset as u64);
                 },
             ),
         );
@@ -253,15 +415,17 @@ impl Instance {
                 store,
                 ins_env,
                 |ins_env: FunctionEnvMut, offset: i32, size: i32| {
-                    let ins_env = ins_env.data();
-                    let key = &ins_env.key;
+                    let ins = ins_env.data();
+                    let key = &ins.key;
                     #[cfg(debug_assertions)]
                     println!(
-                        "[VM:{:?}]_wasmy_vm_restore: wasm_uri={}, offset={}, size={}",
-                        key.thread_id, key.wasm_uri, offset, size
+                        "[VM:{}]_wasmy_vm_restore: wasm_uri={}, offset={}, size={}",
+                        key.id, key.wasm_uri, offset, size
                     );
-                    let _ = ins_env.use_ctx_swap_memory(size as usize, |buffer| {
-                        ins_env.read_memory_bytes(offset as u64, size as usize, buffer);
+                    let memory = ins.memory();
+                    let view = memory.view(&ins_env);
+                    let _ = ins.use_ctx_swap_memory(size as usize, |buffer| {
+                        read_view(&view, offset as u64, size as usize, buffer);
                         buffer.len()
                     });
                 },
@@ -273,18 +437,113 @@ impl Instance {
                 store,
                 ins_env,
                 |ins_env: FunctionEnvMut, offset: i32, size: i32| -> i32 {
-                    let ins_env = ins_env.data();
-                    let key = &ins_env.key;
+                    let ins = ins_env.data();
+                    let key = &ins.key;
+                    #[cfg(debug_assertions)]
+                    println!(
+                        "[VM:{}]_wasmy_vm_invoke: wasm_uri={}, offset={}, size={}",
+                        key.id, key.wasm_uri, offset, size
+                    );
+                    let rets = ins.invoke_vm(&ins.memory().view(&ins_env), offset, size);
+                    // the wasm of ABI 1.2 and before recalls the rets
+                    context::write_to_vec(&rets, &mut ins.context.borrow_mut().swap_memory) as i32
+                },
+            ),
+        );
+        env_namespace.insert(
+            "_wasmy_vm_call",
+            Function::new_typed_with_env(
+                store,
+                ins_env,
+                |mut ins_env: FunctionEnvMut, offset: i32, size: i32| -> i64 {
+                    let (alloc, rets) = {
+                        let ins = ins_env.data();
+                        let key = &ins.key;
+                        #[cfg(debug_assertions)]
+                        println!(
+                            "[VM:{}]_wasmy_vm_call: wasm_uri={}, offset={}, size={}",
+                            key.id, key.wasm_uri, offset, size
+                        );
+                        let rets = ins.invoke_vm(&ins.memory().view(&ins_env), offset, size);
+                        (ins.guest_alloc(), rets)
+                    };
+                    // the store of the instance is borrowed by the running call,
+                    // so the allocator runs with the one of the import
+                    let written = alloc.and_then(|alloc| alloc.write_message(&mut ins_env, &rets));
+                    let rets = match written {
+                        Ok((offset, size)) => {
+                            RawRets { offset: offset as u32, size: size as u32, is_err: false }
+                        }
+                        Err(_) => RawRets { offset: 0, size: 0, is_err: true },
+                    };
+                    rets.to_i64()
+                },
+            ),
+        );
+        env_namespace.insert(
+            "_wasmy_vm_stream_next",
+            Function::new_typed_with_env(store, ins_env, |mut ins_env: FunctionEnvMut| -> i64 {
+                let (alloc, chunk) = {
+                    let ins = ins_env.data();
+                    let key = &ins.key;
+                    #[cfg(debug_assertions)]
+                    println!("[VM:{}]_wasmy_vm_stream_next: wasm_uri={}", key.id, key.wasm_uri);
+                    let chunk = StreamScope::with(&ins.context, |stream| stream?.next());
+                    (ins.guest_alloc(), chunk)
+                };
+                // the allocator runs with the store of the import, as in `_wasmy_vm_call`
+                let written = chunk
+                    .map(|chunk| alloc.and_then(|alloc| alloc.write_bytes(&mut ins_env, &chunk)));
+                let rets = match written {
+                    // the end of the input
+                    None => RawRets { offset: 0, size: 0, is_err: false },
+                    Some(Ok((offset, size))) => {
+                        RawRets { offset: offset as u32, size: size as u32, is_err: false }
+                    }
+                    Some(Err(_)) => RawRets { offset: 0, size: 0, is_err: true },
+                };
+                rets.to_i64()
+            }),
+        );
+        env_namespace.insert(
+            "_wasmy_vm_stream_emit",
+            Function::new_typed_with_env(
+                store,
+                ins_env,
+                |ins_env: FunctionEnvMut, offset: i32, size: i32| -> i32 {
+                    let ins = ins_env.data();
+                    let key = &ins.key;
                     #[cfg(debug_assertions)]
                     println!(
-                        "[VM:{:?}]_wasmy_vm_invoke: wasm_uri={}, offset={}, size={}",
-                        key.thread_id, key.wasm_uri, offset, size
+                        "[VM:{}]_wasmy_vm_stream_emit: wasm_uri={}, offset={}, size={}",
+                        key.id, key.wasm_uri, offset, size
                     );
-                    let ctx_ptr = ins_env.context.borrow().value_ptr;
-                    ins_env.use_ctx_swap_memory(size as usize, |buffer| {
-                        ins_env.read_memory_bytes(offset as u64, size as usize, buffer);
-                        context::write_to_vec(&vm_invoke(ctx_ptr, buffer), buffer)
-                    }) as i32
+                    StreamScope::with(&ins.context, |stream| match stream {
+                        Some(stream) => {
+                            let memory = ins.memory();
+                            let view = memory.view(&ins_env);
+                            // the offset and the size are unsigned in the linear memory
+                            let start = offset as u32 as usize;
+                            let end = start.checked_add(size as u32 as usize);
+                            // SAFETY: the wasm does not run while the slice is borrowed
+                            let data = unsafe { view.data_unchecked() };
+                            match end.and_then(|end| data.get(start..end)) {
+                                Some(chunk) => stream.emit(chunk),
+                                None => CODE_MEM,
+                            }
+                        }
+                        None => CODE_NONE,
+                    })
+                },
+            ),
+        );
+        env_namespace.insert(
+            INTERRUPT_IMPORT,
+            Function::new_typed_with_env(
+                store,
+                ins_env,
+                |ins_env: FunctionEnvMut| -> std::result::Result<(), RuntimeError> {
+                    check_interrupt(ins_env.data().cancel.as_ref())
                 },
             ),
         );
@@ -292,14 +551,17 @@ impl Instance {
         Ok((wasi_env, imports))
     }
 
-    fn into_init(mut self, ins_env: FunctionEnv) -> Result<()> {
-        let ret = self.raw_call_wasm(WasmHandlerApi::onload_symbol(), &[]).map_or_else(
+    fn into_init(self) -> Result<Box<Instance>> {
+        let mut ins = Box::new(self);
+        let ptr = ins.as_mut() as *mut Instance;
+        ins.env.clone().as_mut(&mut ins.store).set(ptr);
+        ins.raw_call_wasm(WasmHandlerApi::onload_symbol(), &[]).map_or_else(
             |e| {
                 if e.code == CODE_NONE {
                     #[cfg(debug_assertions)]
                     println!(
-                        "[{:?}]no need initialize instance: wasm_uri={}",
-                        self.key.thread_id, self.key.wasm_uri
+                        "[{}]no need initialize instance: wasm_uri={}",
+                        ins.key.id, ins.key.wasm_uri
                     );
                     Ok(())
                 } else {
@@ -308,20 +570,11 @@ impl Instance {
             },
             |_| {
                 #[cfg(debug_assertions)]
-                println!(
-                    "[{:?}]initialized instance: wasm_uri={}",
-                    self.key.thread_id, self.key.wasm_uri
-                );
+                println!("[{}]initialized instance: wasm_uri={}", ins.key.id, ins.key.wasm_uri);
                 Ok(())
             },
-        );
-        let mut x = INSTANCES.write().unwrap();
-        let key = self.key.clone();
-        x.insert(key.clone(), Mutex::new(Box::new(self)));
-        let ins = x.get_mut(&key).unwrap().get_mut().unwrap().as_mut();
-        let ptr = ins as *mut Instance;
-        ins_env.as_mut(&mut ins.store).set(ptr);
-        ret
+        )?;
+        Ok(ins)
     }
 
     #[inline]
@@ -336,21 +589,260 @@ impl Instance {
     ) -> Result<OutRets> {
         self.inner_handle_wasm(Some(ctx_value), in_args)
     }
+    /// Handle the wasm method exported by name, whose `InArgs` carries the
+    /// method id of the name.
+    #[inline]
+    pub(crate) fn named_handle_wasm<C: Message>(
+        &mut self,
+        ctx_value: Option<C>,
+        name: &str,
+        in_args: InArgs,
+    ) -> Result<OutRets> {
+        let sign_name = WasmHandlerApi::name_to_symbol(name);
+        self.symbol_handle_wasm(ctx_value, &sign_name, in_args)
+    }
     #[inline]
     fn inner_handle_wasm<C: Message>(
         &mut self,
         ctx_value: Option<C>,
         in_args: InArgs,
+    ) -> Result<OutRets> {
+        let sign_name = WasmHandlerApi::method_to_symbol(in_args.get_method());
+        self.symbol_handle_wasm(ctx_value, &sign_name, in_args)
+    }
+    #[inline]
+    fn symbol_handle_wasm<C: Message>(
+        &mut self,
+        ctx_value: Option<C>,
+        sign_name: &str,
+        in_args: InArgs,
     ) -> Result<OutRets> {
         #[cfg(debug_assertions)]
         println!("method={}, data={:?}", in_args.get_method(), in_args.get_data());
-        let sign_name = WasmHandlerApi::method_to_symbol(in_args.get_method());
-        let (ctx_size, args_size) = self.context.borrow_mut().set_args(ctx_value.as_ref(), in_args);
+        if self.abi_version < HOST_ALLOC_ABI_VERSION {
+            let (ctx_size, args_size) = self.context.borrow_mut().set_args(ctx_value, in_args);
+            self.raw_call_wasm(
+                sign_name,
+                &[Value::I32(ctx_size as i32), Value::I32(args_size as i32)],
+            )?;
+        } else {
+            let ctx_size = self.context.borrow_mut().set_ctx(ctx_value);
+            // the args are placed right into the wasm, which owns them
+            let (offset, args_size) = self.write_to_wasm(&in_args)?;
+            self.raw_call_wasm(
+                sign_name,
+                &[Value::I32(ctx_size as i32), Value::I32(offset), Value::I32(args_size)],
+            )?;
+        }
+        Ok(self.context.borrow_mut().out_rets())
+    }
+    /// Handle the raw bytes handler of the method symbol: the args are written
+    /// into a buffer allocated by the wasm, and the callback reads the rets
+    /// right out of the linear memory before the wasm frees them.
+    pub(crate) fn raw_bytes_handle_wasm<F, R>(
+        &mut self,
+        sign_name: &str,
+        args: &[u8],
+        callback: F,
+    ) -> Result<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        #[cfg(debug_assertions)]
+        println!("sign_name={}, size={}", sign_name, args.len());
+        if self.abi_version < RAW_BYTES_ABI_VERSION {
+            return CodeMsg::result(
+                CODE_VERSION,
+                format!(
+                    "the raw bytes handlers require ABI {}, but the wasm is built with {}",
+                    RAW_BYTES_ABI_VERSION, self.abi_version
+                ),
+            );
+        }
+        let (offset, size) = self.write_bytes_to_wasm(args)?;
+        let rets = self.raw_call_wasm(sign_name, &[Value::I32(offset), Value::I32(size)])?;
+        self.take_raw_rets(sign_name, &rets, callback)
+    }
+    /// Read the bytes returned by the raw bytes function, then free them.
+    fn take_raw_rets<F, R>(&mut self, sign_name: &str, rets: &[Value], callback: F) -> Result<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let rets = match rets[..] {
+            [Value::I64(rets)] => RawRets::from_i64(rets),
+            _ => {
+                return CodeMsg::result(CODE_EXPORTS, format!("{} does not return i64", sign_name));
+            }
+        };
+        let ret = {
+            let view = self.get_view();
+            let start = rets.offset as usize;
+            // SAFETY: the wasm does not run while the slice is borrowed
+            match unsafe { view.data_unchecked() }.get(start..start + rets.size as usize) {
+                Some(data) if rets.is_err => match OutRets::parse_from_bytes(data) {
+                    Ok(out_rets) => Err(out_rets.into()),
+                    Err(err) => Err(CodeMsg::new(CODE_PROTO, err)),
+                },
+                Some(data) => Ok(callback(data)),
+                None => CodeMsg::result(CODE_MEM, format!("{} returns out of bounds", sign_name)),
+            }
+        };
         self.raw_call_wasm(
-            sign_name.as_str(),
-            &[Value::I32(ctx_size as i32), Value::I32(args_size as i32)],
+            WasmHandlerApi::dealloc_symbol(),
+            &[Value::I32(rets.offset as i32), Value::I32(rets.size as i32)],
         )?;
-        Ok(self.context.borrow_mut().out_rets())
+        ret
+    }
+    /// Handle the stream handler of the method symbol, which pulls the input
+    /// chunks and pushes the output chunks through the stream imports.
+    pub(crate) fn stream_handle_wasm(
+        &mut self,
+        sign_name: &str,
+        input: StreamInput,
+        output: StreamOutput,
+    ) -> Result<()> {
+        #[cfg(debug_assertions)]
+        println!("sign_name={}", sign_name);
+        if self.abi_version < STREAM_ABI_VERSION {
+            return CodeMsg::result(
+                CODE_VERSION,
+                format!(
+                    "the stream handlers require ABI {}, but the wasm is built with {}",
+                    STREAM_ABI_VERSION, self.abi_version
+                ),
+            );
+        }
+        let state = Arc::new(Mutex::new(StreamState::new(input, output)));
+        let ctx_size = self.context.borrow_mut().set_ctx(None::<Empty>);
+        let f = self
+            .instance
+            .exports
+            .get_function(self.manifest.export_symbol(sign_name))
+            .map_err(|e| CodeMsg::new(CODE_NONE, e))?;
+        let ret = {
+            // the stream imports reach the state only within the call
+            let _scope = StreamScope::enter(&self.context, &state);
+            f.call(&mut self.store, &[Value::I32(ctx_size as i32)]).map_err(CodeMsg::from)
+        };
+        let ret = self.check_trap(sign_name, ret);
+        let out_rets = self.context.borrow_mut().out_rets();
+        // the refused output fails the call, whatever the wasm does with it
+        if let Some(err) = state.lock().unwrap().take_error() {
+            return Err(err);
+        }
+        ret?;
+        if out_rets.get_code() != 0 {
+            return Err(out_rets.into());
+        }
+        Ok(())
+    }
+    /// Set the fuel of the next call.
+    /// NOTE: The wasm must be loaded with metering.
+    pub fn set_fuel(&mut self, points: u64) -> Result<()> {
+        if self.metering.is_none() {
+            return CodeMsg::result(CODE_FUEL, "the wasm is not loaded with metering");
+        }
+        set_remaining_points(&mut self.store, &self.instance, points);
+        Ok(())
+    }
+    /// Get the fuel left by the last call, or none if the wasm is not loaded
+    /// with metering.
+    pub fn remaining_fuel(&mut self) -> Option<u64> {
+        self.metering.as_ref()?;
+        match get_remaining_points(&mut self.store, &self.instance) {
+            MeteringPoints::Remaining(points) => Some(points),
+            MeteringPoints::Exhausted => Some(0),
+        }
+    }
+    /// Run the callback with the host state of the call, which is given back
+    /// after the call.
+    pub(crate) fn with_call_state<F, R>(
+        &mut self,
+        state: &mut HostContext,
+        callback: F,
+    ) -> Result<R>
+    where
+        F: FnOnce(&mut Instance) -> Result<R>,
+    {
+        mem::swap(&mut self.mut_context().host, state);
+        let ret = callback(self);
+        mem::swap(&mut self.mut_context().host, state);
+        ret
+    }
+    /// Get the host state of the instance.
+    pub fn mut_state(&self) -> RefMut<'_, HostContext> {
+        RefMut::map(self.context.borrow_mut(), |ctx| &mut ctx.state)
+    }
+    /// The ABI version that the wasm is built with.
+    pub fn abi_version(&self) -> AbiVersion {
+        self.abi_version
+    }
+    /// Whether both the wasm and the vm support the codec.
+    pub fn supports_codec(&self, kind: CodecKind) -> bool {
+        self.codecs & kind.bit() != 0
+    }
+    pub(crate) fn check_codec(&self, kind: CodecKind) -> Result<()> {
+        if self.supports_codec(kind) {
+            return Ok(());
+        }
+        CodeMsg::result(
+            CODE_PROTO,
+            format!("the {} codec is not supported by both the wasm and the vm", kind.name()),
+        )
+    }
+    /// Call the exported function of `fn() -> i32`, none if it is not exported.
+    fn read_i32_export(&mut self, symbol: &str) -> Result<Option<i32>> {
+        let function = match self.instance.exports.get_function(symbol) {
+            Ok(function) => function.clone(),
+            Err(_) => return Ok(None),
+        };
+        match function.call(&mut self.store, &[])?.first() {
+            Some(Value::I32(value)) => Ok(Some(*value)),
+            _ => CodeMsg::result(CODE_EXPORTS, format!("{} does not return i32", symbol)),
+        }
+    }
+    /// Read the message types exported by `_wasmy_manifest` into the manifest.
+    fn read_types(&mut self) -> Result<()> {
+        let symbol = WasmHandlerApi::manifest_symbol();
+        if self.instance.exports.get_function(symbol).is_err() {
+            return Ok(());
+        }
+        let rets = self.raw_call_wasm(symbol, &[])?;
+        let mut manifest = (*self.manifest).clone();
+        self.take_raw_rets(symbol, &rets, |data| manifest.insert_types_of(data))??;
+        self.manifest = Arc::new(manifest);
+        Ok(())
+    }
+    /// Whether a call of the instance has been interrupted, leaving it in an
+    /// inconsistent state.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+    /// Run the callback, interrupting the guest once the handle is canceled.
+    /// The interrupted instance is poisoned.
+    pub(crate) fn run_cancellable<F, R>(&mut self, handle: &CancelHandle, callback: F) -> Result<R>
+    where
+        F: FnOnce(&mut Instance) -> Result<R>,
+    {
+        if !self.interruptible {
+            return CodeMsg::result(CODE_CANCELED, "the wasm is not loaded interruptible");
+        }
+        if let Some(code) = handle.code() {
+            return interrupted(code);
+        }
+        // the guest checks the handle through the env, never the store
+        self.env.clone().as_mut(&mut self.store).cancel = Some(handle.clone());
+        let ret = callback(self);
+        self.env.clone().as_mut(&mut self.store).cancel = None;
+        match (ret, handle.code()) {
+            (Err(_), Some(code)) => {
+                self.poisoned = true;
+                #[cfg(debug_assertions)]
+                println!("[{}]poisoned instance: wasm_uri={}", self.key.id, self.key.wasm_uri);
+                interrupted(code)
+            }
+            (ret, _) => ret,
+        }
     }
     pub fn exports(&self) -> &Exports {
         &self.instance.exports
@@ -363,67 +855,181 @@ impl Instance {
         sign_name: &str,
         args: &[Value],
     ) -> Result<Box<[Value]>> {
-        let exports = &mut self.instance.exports;
-        let store = &mut self.store;
-        let f = exports.get_function(sign_name).map_err(|e| CodeMsg::new(CODE_NONE, e))?;
-        loop {
-            let rets = f.call(store, &args);
-            match rets {
-                Ok(r) => return Ok(r),
-                Err(e) => {
-                    let estr = format!("{:?}", e);
-                    if !estr.contains("OOM") {
-                        return CodeMsg::from(e).into_result();
-                    }
-                    eprintln!("call {} error: {}", sign_name, estr);
-                    match exports.get_memory("memory").unwrap().grow(store, 1) {
-                        Ok(p) => {
-                            println!("memory grow, previous memory size: {:?}", p);
-                        }
-                        Err(e) => {
-                            return CodeMsg::result(
-                                CODE_MEM,
-                                format!("failed to memory grow: {:?}", e),
-                            );
-                        }
-                    }
-                }
+        let f = self
+            .instance
+            .exports
+            .get_function(self.manifest.export_symbol(sign_name))
+            .map_err(|e| CodeMsg::new(CODE_NONE, e))?;
+        let ret = f.call(&mut self.store, args).map_err(CodeMsg::from);
+        self.check_trap(sign_name, ret)
+    }
+    /// Tell the exhaustion of the fuel and of the memory apart from the other
+    /// failures of calling the wasm function.
+    fn check_trap<R>(&mut self, sign_name: &str, ret: Result<R>) -> Result<R> {
+        let grow_failed = self.grow_failure.take();
+        let e = match ret {
+            Ok(r) => return Ok(r),
+            Err(e) => e,
+        };
+        if self.metering.is_some() {
+            if let MeteringPoints::Exhausted = get_remaining_points(&mut self.store, &self.instance)
+            {
+                return CodeMsg::result(
+                    CODE_FUEL,
+                    format!("fuel exhausted when calling {}", sign_name),
+                );
             }
         }
+        // the guest aborts when its allocator fails to grow the memory, and
+        // it cannot be reused as the memory stays exhausted
+        if grow_failed {
+            self.poisoned = true;
+            return CodeMsg::result(
+                CODE_MEM,
+                format!("memory limit exceeded when calling {}: {}", sign_name, e),
+            );
+        }
+        Err(e)
     }
 
-    fn ctx_write_to(&self, is_ctx: bool, offset: u64) {
+    fn ctx_write_to(&self, view: &MemoryView, is_ctx: bool, offset: u64) {
         let mut ctx = self.context.borrow_mut();
         let cache: &mut Vec<u8> =
             if is_ctx { ctx.value_bytes.as_mut() } else { ctx.swap_memory.as_mut() };
-        self.write_memory_bytes(offset, cache.as_slice());
+        view.write(offset, cache.as_slice()).unwrap();
         if !is_ctx {
             unsafe {
                 cache.set_len(0);
             }
         }
     }
-    fn use_ctx_swap_memory<F: FnOnce(&mut Vec<u8>) -> usize>(&self, size: usize, call: F) -> usize {
+    fn use_ctx_swap_memory<F: FnOnce(&mut Vec<u8>) -> R, R>(&self, size: usize, call: F) -> R {
         let mut ctx = self.context.borrow_mut();
         let cache: &mut Vec<u8> = ctx.swap_memory.as_mut();
         if size > 0 {
             context::resize_with_capacity(cache, size);
         }
-        return call(cache);
+        call(cache)
+    }
+    /// Invoke the vm handler with the args in the linear memory. The host
+    /// values are taken out of the context, as the handler may use it.
+    fn invoke_vm(&self, view: &MemoryView, offset: i32, size: i32) -> OutRets {
+        let (ctx_value, mut host, mut state) = {
+            let mut ctx = self.context.borrow_mut();
+            (mem::take(&mut ctx.ctx_value), mem::take(&mut ctx.host), mem::take(&mut ctx.state))
+        };
+        let mut env = HostEnv { ctx: &ctx_value, call: &mut host, instance: &mut state };
+        let rets = self.use_ctx_swap_memory(size as usize, |buffer| {
+            read_view(view, offset as u64, size as usize, buffer);
+            vm_invoke(self.handlers.as_deref(), &mut env, buffer)
+        });
+        let mut ctx = self.context.borrow_mut();
+        ctx.ctx_value = ctx_value;
+        ctx.host = host;
+        ctx.state = state;
+        rets
+    }
+    /// The guest allocator, cloned out of the exports so that it can also run
+    /// with the store of an import.
+    fn guest_alloc(&self) -> Result<GuestAlloc> {
+        let alloc = self
+            .instance
+            .exports
+            .get_function(WasmHandlerApi::alloc_symbol())
+            .map_err(|e| CodeMsg::new(CODE_NONE, e))?;
+        Ok(GuestAlloc { alloc: alloc.clone(), memory: self.memory() })
+    }
+    /// Write the message into a buffer allocated in the wasm, whose ownership
+    /// passes to the wasm, returning the offset and the size of the buffer.
+    fn write_to_wasm<M: Message>(&mut self, msg: &M) -> Result<(i32, i32)> {
+        let ret = self.guest_alloc()?.write_message(&mut self.store, msg);
+        self.check_trap(WasmHandlerApi::alloc_symbol(), ret)
+    }
+    /// Write the bytes into a buffer allocated in the wasm, whose ownership
+    /// passes to the wasm, returning the offset and the size of the buffer.
+    fn write_bytes_to_wasm(&mut self, data: &[u8]) -> Result<(i32, i32)> {
+        let ret = self.guest_alloc()?.write_bytes(&mut self.store, data);
+        self.check_trap(WasmHandlerApi::alloc_symbol(), ret)
+    }
+    fn memory(&self) -> Memory {
+        self.instance.exports.get_memory("memory").unwrap().clone()
     }
-    fn get_view(&self) -> MemoryView {
+    fn get_view(&self) -> MemoryView<'_> {
         self.instance.exports.get_memory("memory").unwrap().view(&self.store)
     }
-    pub fn write_memory_bytes<'a>(&self, offset: u64, data: &[u8]) {
+    pub fn write_memory_bytes(&self, offset: u64, data: &[u8]) {
         self.get_view().write(offset, data).unwrap();
     }
     pub fn read_memory_bytes(&self, offset: u64, size: usize, buffer: &mut Vec<u8>) {
-        if size == 0 {
-            context::resize_with_capacity(buffer, size);
-            return;
-        }
-        self.get_view().read(offset, buffer).unwrap();
+        read_view(&self.get_view(), offset, size, buffer)
+    }
+}
+
+fn read_view(view: &MemoryView, offset: u64, size: usize, buffer: &mut Vec<u8>) {
+    if size == 0 {
+        context::resize_with_capacity(buffer, size);
+        return;
     }
+    view.read(offset, buffer).unwrap();
+}
+
+/// The guest allocator and the linear memory of an instance. It writes with
+/// the store that it is given: the one of the instance when the vm calls the
+/// wasm, or the one of the import when the wasm calls the vm, as the store of
+/// the instance is borrowed by the running call then.
+struct GuestAlloc {
+    alloc: Function,
+    memory: Memory,
+}
+
+impl GuestAlloc {
+    /// Allocate a buffer of the size with `_wasmy_alloc` and fill it, whose
+    /// ownership passes to the wasm, returning the offset of the buffer.
+    fn write<F>(&self, store: &mut impl AsStoreMut, size: usize, fill: F) -> Result<i32>
+    where
+        F: FnOnce(&mut [u8]) -> Result<()>,
+    {
+        let offset = match self.alloc.call(store, &[Value::I32(size as i32)])?[..] {
+            [Value::I32(offset)] => offset,
+            _ => return CodeMsg::result(CODE_EXPORTS, "_wasmy_alloc does not return i32"),
+        };
+        let out_of_bounds = || CodeMsg::new(CODE_MEM, "_wasmy_alloc returns out of bounds");
+        let start = u32::try_from(offset).map_err(|_| out_of_bounds())? as usize;
+        let view = self.memory.view(store);
+        // SAFETY: the wasm does not run while the slice is borrowed
+        let data = unsafe { view.data_unchecked_mut() };
+        let buffer = start
+            .checked_add(size)
+            .and_then(|end| data.get_mut(start..end))
+            .ok_or_else(out_of_bounds)?;
+        fill(buffer)?;
+        Ok(offset)
+    }
+    fn write_message<M: Message>(
+        &self,
+        store: &mut impl AsStoreMut,
+        msg: &M,
+    ) -> Result<(i32, i32)> {
+        let size = msg.compute_size() as usize;
+        let offset = self.write(store, size, |buffer| {
+            let mut os = CodedOutputStream::bytes(buffer);
+            msg.write_to_with_cached_sizes(&mut os).map_err(|e| CodeMsg::new(CODE_PROTO, e))?;
+            os.flush().map_err(|e| CodeMsg::new(CODE_PROTO, e))
+        })?;
+        Ok((offset, size as i32))
+    }
+    fn write_bytes(&self, store: &mut impl AsStoreMut, data: &[u8]) -> Result<(i32, i32)> {
+        let offset = self.write(store, data.len(), |buffer| {
+            buffer.copy_from_slice(data);
+            Ok(())
+        })?;
+        Ok((offset, data.len() as i32))
+    }
+}
+
+fn interrupted<R>(code: RetCode) -> Result<R> {
+    let reason = if code == CODE_TIMEOUT { "timed out" } else { "canceled" };
+    CodeMsg::result(code, format!("call interrupted: {}", reason))
 }
 
 fn default_imports(
@@ -440,3 +1046,199 @@ fn default_imports(
     let imports = wasi_env.import_object(store, module)?;
     Ok((wasi_env, imports))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use wasmy_abi::test::{TestMethods, TestNested_Methods};
+
+    use crate::{
+        load_wasm, load_wasm_with, register_file, Empty, InArgs, LoadOptions, PoolConfig,
+        ResourceLimits, WasmUri, CODE_EXPORTS, CODE_MEM, CODE_PROTO, CODE_WASI,
+    };
+
+    /// A wasm of ABI 1.0, whose method 1 traps.
+    const FAIL_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (memory (export "memory") 1)
+        (func $fail unreachable)
+        (func (export "_wasmy_wasm_handle_1") (param i32 i32) call $fail)
+        (func (export "_wasmy_wasm_handle_2") (param i32 i32)))"#;
+
+    #[test]
+    fn failed_call_context() {
+        let wasm = wat::parse_str(FAIL_WAT).unwrap();
+        let caller = load_wasm(("failed_call_test", wasm)).unwrap();
+        let mut ctx = InArgs::new();
+        ctx.set_method(7);
+        assert!(caller.ctx_call::<InArgs, Empty, Empty>(ctx, 1, Empty::new()).is_err());
+        // the next call of the instance sees nothing of the failed one
+        caller
+            .with(|ins| {
+                let ctx = ins.mut_context();
+                assert!(ctx.ctx_value.is_empty());
+                assert!(ctx.value_bytes.is_empty() && ctx.swap_memory.is_empty());
+                Ok(())
+            })
+            .unwrap();
+        let _ = caller.call::<Empty, Empty>(2, Empty::new());
+        assert_eq!(caller.pool_metrics().unwrap().created, 1);
+    }
+
+    #[test]
+    fn pool_min_size() {
+        let wasm = wat::parse_str(FAIL_WAT).unwrap();
+        let pool = PoolConfig { min_size: 3, max_size: 4, ..Default::default() };
+        let options = LoadOptions { pool, ..Default::default() };
+        let caller = load_wasm_with(("pool_min_size_test", wasm), options).unwrap();
+        let metrics = caller.pool_metrics().unwrap();
+        assert_eq!((metrics.created, metrics.idle), (3, 3));
+        let caller = WasmUri::from("pool_not_loaded_test".to_string()).into_caller();
+        let err = caller.call::<Empty, Empty>(2, Empty::new()).unwrap_err();
+        assert_eq!(err.code, CODE_WASI);
+        // a registered file is loaded by the call with the default options
+        let wasm = wat::parse_str(FAIL_WAT).unwrap();
+        let caller = register_file(("pool_registered_test", wasm)).unwrap().into_caller();
+        let err = caller.call::<Empty, Empty>(2, Empty::new()).unwrap_err();
+        assert_ne!(err.code, CODE_WASI);
+        assert_eq!(caller.pool_metrics().unwrap().created, 1);
+    }
+
+    /// A wasm of ABI 1.0 recording its enum methods like
+    /// `#[wasm_handle(M::ADD)]` with `use TestNested_Methods as M`, and
+    /// `#[wasm_handle(TEST_ADD)]` with `use TestMethods::*`, which fail with the
+    /// codes 7 and 8.
+    const ENUM_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (import "env" "_wasmy_vm_restore" (func $restore (param i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "\08\07\08\08")
+        (func (export "_wasmy_abi_version") (result i32) i32.const 65536)
+        (func (export "_wasmy_wasm_enum_M_3a_3aADD") (param i32 i32)
+            i32.const 16 i32.const 2 call $restore)
+        (func (export "_wasmy_wasm_enum_TEST_5fADD") (param i32 i32)
+            i32.const 18 i32.const 2 call $restore)
+        (@custom "wasmy.enums"
+            "\02\00\00\00\1b\00_wasmy_wasm_enum_M_3a_3aADD"
+            "\01\00\00\00\1b\00_wasmy_wasm_enum_TEST_5fADD"))"#;
+
+    #[test]
+    fn enum_methods() {
+        let wasm = wat::parse_str(ENUM_WAT).unwrap();
+        let caller = load_wasm(("enum_methods_test", wasm)).unwrap();
+        let code = |ret: crate::Result<Empty>| ret.unwrap_err().code;
+        // the nested and the aliased enums are called by their values
+        assert_eq!(code(caller.call_enum(TestNested_Methods::ADD, Empty::new())), 7);
+        assert_eq!(code(caller.call(TestNested_Methods::ADD as i32, Empty::new())), 7);
+        assert_eq!(code(caller.call_enum(TestMethods::TEST_ADD, Empty::new())), 8);
+        let methods = caller.describe().unwrap().methods;
+        assert_eq!(methods.iter().map(|m| m.method).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    /// A wasm exporting the message types of its method 1 like
+    /// `#[wasm_handle(1)] fn f(ctx: WasmCtx, args: Empty) -> Result<InArgs>`.
+    const TYPES_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "\14\00_wasmy_wasm_handle_1\09\00abi.Empty\0a\00abi.InArgs")
+        (func (export "_wasmy_wasm_handle_1") (param i32 i32))
+        (func (export "_wasmy_manifest") (result i64) i64.const 0x100000002d)
+        (func (export "_wasmy_dealloc") (param i32 i32)))"#;
+
+    #[test]
+    fn message_types() {
+        let wasm = wat::parse_str(TYPES_WAT).unwrap();
+        let caller = load_wasm(("message_types_test", wasm)).unwrap();
+        let err = caller.call::<InArgs, InArgs>(1, InArgs::new()).unwrap_err();
+        assert_eq!(err.code, CODE_PROTO);
+        let ret = caller.call::<Empty, InArgs>(1, Empty::new());
+        assert_ne!(ret.map_err(|e| e.code), Err(CODE_PROTO));
+        let types = caller.describe().unwrap().methods[0].types.clone().unwrap();
+        assert_eq!(types.args.as_deref(), Some("abi.Empty"));
+        assert_eq!(types.rets.as_deref(), Some("abi.InArgs"));
+    }
+
+    /// A wasm of ABI 1.3 whose allocator returns a negative offset.
+    const ALLOC_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (memory (export "memory") 1)
+        (func (export "_wasmy_abi_version") (result i32) i32.const 65539)
+        (func (export "_wasmy_alloc") (param i32) (result i32) i32.const -1)
+        (func (export "_wasmy_dealloc") (param i32 i32))
+        (func (export "_wasmy_wasm_handle_1") (param i32 i32 i32)))"#;
+
+    #[test]
+    fn handle_params() {
+        let text = ALLOC_WAT.replace("param i32 i32 i32", "param i32 i32");
+        let err = load_wasm(("handle_params_test", wat::parse_str(text).unwrap())).unwrap_err();
+        assert_eq!(err.code, CODE_EXPORTS);
+        // the wasm of ABI 1.0 does not pass the offset of the args
+        let text = FAIL_WAT.replace("param i32 i32)", "param i32 i32 i32)");
+        let err = load_wasm(("handle_params_test", wat::parse_str(text).unwrap())).unwrap_err();
+        assert_eq!(err.code, CODE_EXPORTS);
+    }
+
+    #[test]
+    fn alloc_out_of_bounds() {
+        let wasm = wat::parse_str(ALLOC_WAT).unwrap();
+        let caller = load_wasm(("alloc_out_of_bounds_test", wasm)).unwrap();
+        let err = caller.call::<Empty, Empty>(1, Empty::new()).unwrap_err();
+        assert_eq!(err.code, CODE_MEM);
+    }
+
+    /// A wasm of ABI 1.4 whose stream method 1 emits a chunk, then traps unless
+    /// the chunks out of the memory fail with `CODE_MEM`.
+    const STREAM_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (import "env" "_wasmy_vm_stream_emit" (func $emit (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "\01\02")
+        (func (export "_wasmy_abi_version") (result i32) i32.const 65540)
+        (func $check (param i32 i32)
+            local.get 0 local.get 1 call $emit i32.const -9 i32.ne if unreachable end)
+        (func (export "_wasmy_wasm_stream_handle_1") (param i32)
+            i32.const 16 i32.const 2 call $emit drop
+            i32.const -1 i32.const 1 call $check
+            i32.const 16 i32.const -1 call $check
+            i32.const -1 i32.const -1 call $check))"#;
+
+    #[test]
+    fn stream_emit_out_of_bounds() {
+        let wasm = wat::parse_str(STREAM_WAT).unwrap();
+        let caller = load_wasm(("stream_emit_test", wasm)).unwrap();
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let output = {
+            let emitted = emitted.clone();
+            move |chunk: &[u8]| {
+                emitted.lock().unwrap().push(chunk.to_vec());
+                Ok(())
+            }
+        };
+        caller.call_stream(1, vec![], output).unwrap();
+        assert_eq!(*emitted.lock().unwrap(), vec![vec![1, 2]]);
+    }
+
+    /// A wasm whose method 1 traps when it fails to grow the memory, and whose
+    /// method 2 traps anyway.
+    const GROW_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (memory (export "memory") 1)
+        (func (result i32) i32.const 0)
+        (func (export "_wasmy_wasm_handle_1") (param i32 i32)
+            i32.const 1 memory.grow i32.const -1 i32.eq if unreachable end)
+        (func (export "_wasmy_wasm_handle_2") (param i32 i32) unreachable))"#;
+
+    #[test]
+    fn memory_exhausted() {
+        let wasm = wat::parse_str(GROW_WAT).unwrap();
+        let limits = ResourceLimits { max_memory_pages: Some(1), ..Default::default() };
+        let options = LoadOptions { limits, ..Default::default() };
+        let caller = load_wasm_with(("memory_exhausted_test", wasm), options).unwrap();
+        // the memory at the limit does not make any trap out of memory
+        let err = caller.call::<Empty, Empty>(2, Empty::new()).unwrap_err();
+        assert_ne!(err.code, CODE_MEM);
+        let err = caller.call::<Empty, Empty>(1, Empty::new()).unwrap_err();
+        assert_eq!(err.code, CODE_MEM);
+    }
+}
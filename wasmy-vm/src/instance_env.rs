@@ -3,11 +3,13 @@ use std::{
     ops::{Deref, DerefMut},
 };
 
-use crate::Instance;
+use crate::{interrupt::CancelHandle, Instance};
 
 #[derive(Clone, Debug)]
 pub struct InstanceEnv {
     ptr: *mut Instance,
+    /// The handle of the running cancellable call.
+    pub(crate) cancel: Option<CancelHandle>,
 }
 
 unsafe impl Sync for InstanceEnv {}
@@ -22,13 +24,15 @@ impl InstanceEnv {
 
 impl Default for InstanceEnv {
     fn default() -> Self {
-        unsafe { InstanceEnv { ptr: alloc(Layout::new::<Instance>()) as *mut Instance } }
+        unsafe {
+            InstanceEnv { ptr: alloc(Layout::new::<Instance>()) as *mut Instance, cancel: None }
+        }
     }
 }
 
 impl From<&mut Instance> for InstanceEnv {
     fn from(ins: &mut Instance) -> Self {
-        InstanceEnv { ptr: ins as *mut Instance }
+        InstanceEnv { ptr: ins as *mut Instance, cancel: None }
     }
 }
 
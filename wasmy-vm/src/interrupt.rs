@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use wasmer::{
+    wasmparser::{Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType},
+    ExportIndex, FunctionMiddleware, FunctionType, GlobalInit, GlobalType, LocalFunctionIndex,
+    MiddlewareError, MiddlewareReaderState, ModuleMiddleware, Mutability, RuntimeError, Type,
+};
+use wasmer_types::{
+    entity::EntityRef, FunctionIndex, GlobalIndex, ImportIndex, ImportKey, ModuleInfo,
+};
+use wasmy_abi::*;
+
+/// The import called by the injected check, which traps once the running call
+/// is interrupted.
+pub(crate) const INTERRUPT_IMPORT: &str = "_wasmy_vm_interrupted";
+
+/// The injected check calls the import once every so many ticks, where a tick
+/// is the entry of a function or the head of a loop.
+const TICKS_PER_CHECK: i32 = 1024;
+
+/// The middleware counting the ticks at the entry of each function and at the
+/// head of each loop, and calling the `env.{INTERRUPT_IMPORT}` import once
+/// every `TICKS_PER_CHECK` ticks. The import is added to the module, so the
+/// indices of the local functions are shifted by one. The shifted module info
+/// is checked against the original one, and the compilation fails instead of
+/// running a module that calls the wrong functions.
+#[derive(Debug, Default)]
+pub(crate) struct Interrupt {
+    layout: Mutex<Option<std::result::Result<Layout, String>>>,
+}
+
+/// Where the import and the tick counter are added to the module.
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    import: FunctionIndex,
+    global: GlobalIndex,
+    /// The number of the functions before the import is added.
+    functions: usize,
+}
+
+impl Layout {
+    /// Shift the function behind the added import by one.
+    fn shift(&self, function: FunctionIndex) -> FunctionIndex {
+        if function < self.import {
+            function
+        } else {
+            FunctionIndex::new(function.index() + 1)
+        }
+    }
+    /// Shift the function called by an operator, which must be one of the
+    /// original functions.
+    fn shift_operand(&self, function_index: u32) -> std::result::Result<u32, MiddlewareError> {
+        let function = FunctionIndex::from_u32(function_index);
+        if function.index() >= self.functions {
+            return Err(MiddlewareError::new(
+                "Interrupt",
+                format!("function {} is out of {} functions", function_index, self.functions),
+            ));
+        }
+        Ok(self.shift(function).as_u32())
+    }
+}
+
+struct FunctionInterrupt {
+    layout: std::result::Result<Layout, String>,
+    started: bool,
+}
+
+impl Debug for FunctionInterrupt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FunctionInterrupt").field("layout", &self.layout).finish()
+    }
+}
+
+impl Interrupt {
+    fn layout(&self) -> std::result::Result<Layout, String> {
+        self.layout
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| Err("the module info is not transformed yet".to_string()))
+    }
+}
+
+impl ModuleMiddleware for Interrupt {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionInterrupt { layout: self.layout(), started: false })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut layout = self.layout.lock().unwrap();
+        *layout = Some(match *layout {
+            None => add_import(module_info),
+            // the indices of the first module would be used for this one
+            Some(_) => Err("the middleware is used by multiple modules".to_string()),
+        });
+    }
+}
+
+/// Add the import after the imported functions, shifting the local ones and
+/// everything that refers to them, and the global of the ticks.
+fn add_import(module_info: &mut ModuleInfo) -> std::result::Result<Layout, String> {
+    // the shift relies on the imported functions coming first
+    let imported: Vec<_> = module_info
+        .imports
+        .values()
+        .filter_map(|index| match index {
+            ImportIndex::Function(function) => Some(*function),
+            _ => None,
+        })
+        .collect();
+    if imported.len() != module_info.num_imported_functions
+        || imported.iter().any(|function| function.index() >= imported.len())
+    {
+        return Err("the imported functions are not in front of the local ones".to_string());
+    }
+    let original: Vec<_> = module_info.functions.values().copied().collect();
+    let import = FunctionIndex::new(module_info.num_imported_functions);
+    // the ticks since the last check
+    let global = module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+    module_info.global_initializers.push(GlobalInit::I32Const(0));
+    let layout = Layout { import, global, functions: original.len() };
+    let shift = |function: FunctionIndex| layout.shift(function);
+
+    let signature = module_info.signatures.push(FunctionType::new([], []));
+    let mut functions = original.clone();
+    functions.insert(import.index(), signature);
+    module_info.functions = functions.into_iter().collect();
+    module_info.num_imported_functions += 1;
+    module_info.imports.insert(
+        ImportKey {
+            module: "env".to_string(),
+            field: INTERRUPT_IMPORT.to_string(),
+            import_idx: module_info.imports.len() as u32,
+        },
+        ImportIndex::Function(import),
+    );
+    let mut referred = Vec::new();
+    for export in module_info.exports.values_mut() {
+        if let ExportIndex::Function(function) = export {
+            referred.push(*function);
+            *function = shift(*function);
+        }
+    }
+    referred.extend(module_info.start_function);
+    module_info.start_function = module_info.start_function.map(shift);
+    for initializer in module_info.table_initializers.iter_mut() {
+        referred.extend(initializer.elements.iter().copied());
+        initializer.elements.iter_mut().for_each(|function| *function = shift(*function));
+    }
+    for elements in module_info.passive_elements.values_mut() {
+        referred.extend(elements.iter().copied());
+        elements.iter_mut().for_each(|function| *function = shift(*function));
+    }
+    for initializer in module_info.global_initializers.values_mut() {
+        if let GlobalInit::RefFunc(function) = initializer {
+            referred.push(*function);
+            *function = shift(*function);
+        }
+    }
+    module_info.function_names = module_info
+        .function_names
+        .drain()
+        .map(|(function, name)| (shift(function), name))
+        .collect::<HashMap<_, _>>();
+
+    // every shifted function keeps its signature, and the import is in place
+    let shifted = |function: FunctionIndex| module_info.functions.get(shift(function)).copied();
+    if module_info.functions.get(import) != Some(&signature) {
+        return Err(format!("the import is not added as function {}", import.as_u32()));
+    }
+    if let Some(function) = (0..original.len())
+        .map(FunctionIndex::new)
+        .chain(referred)
+        .find(|function| original.get(function.index()).copied() != shifted(*function))
+    {
+        return Err(format!("function {} is shifted to a wrong one", function.as_u32()));
+    }
+    Ok(layout)
+}
+
+impl FunctionMiddleware for FunctionInterrupt {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> std::result::Result<(), MiddlewareError> {
+        let layout = self.layout.as_ref().map_err(|e| MiddlewareError::new("Interrupt", e))?;
+        let operator = match operator {
+            Operator::Call { function_index } => {
+                Operator::Call { function_index: layout.shift_operand(function_index)? }
+            }
+            Operator::ReturnCall { function_index } => {
+                Operator::ReturnCall { function_index: layout.shift_operand(function_index)? }
+            }
+            Operator::RefFunc { function_index } => {
+                Operator::RefFunc { function_index: layout.shift_operand(function_index)? }
+            }
+            operator => operator,
+        };
+        let is_loop = matches!(operator, Operator::Loop { .. });
+        if is_loop {
+            state.push_operator(operator.clone());
+        }
+        if is_loop || !self.started {
+            self.started = true;
+            let global_index = layout.global.as_u32();
+            state.extend(&[
+                Operator::GlobalGet { global_index },
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::GlobalSet { global_index },
+                Operator::GlobalGet { global_index },
+                Operator::I32Const { value: TICKS_PER_CHECK - 1 },
+                Operator::I32And,
+                Operator::I32Eqz,
+                Operator::If { ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType) },
+                Operator::Call { function_index: layout.import.as_u32() },
+                Operator::End,
+            ]);
+        }
+        if !is_loop {
+            state.push_operator(operator);
+        }
+        Ok(())
+    }
+}
+
+/// A handle to interrupt a running guest call from another thread.
+/// The interrupted instance is dropped instead of being reused.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle(Arc<CancelState>);
+
+#[derive(Debug, Default)]
+struct CancelState {
+    /// The code of the interruption, zero until the call is interrupted.
+    code: AtomicI32,
+    /// The call times out once the deadline passes.
+    deadline: Option<Instant>,
+}
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A handle interrupting the call with `CODE_TIMEOUT` once the timeout
+    /// elapses, which is checked by the running guest itself.
+    pub(crate) fn with_timeout(timeout: Duration) -> Self {
+        CancelHandle(Arc::new(CancelState {
+            code: AtomicI32::new(0),
+            deadline: Instant::now().checked_add(timeout),
+        }))
+    }
+    /// Interrupt the call bound to the handle, or the next one if none is
+    /// running.
+    pub fn cancel(&self) {
+        self.interrupt(CODE_CANCELED)
+    }
+    /// Whether the handle has been canceled or timed out.
+    pub fn is_canceled(&self) -> bool {
+        self.code().is_some()
+    }
+    fn interrupt(&self, code: RetCode) {
+        // the first interruption wins
+        let _ = self.0.code.compare_exchange(0, code, Ordering::SeqCst, Ordering::SeqCst);
+    }
+    pub(crate) fn code(&self) -> Option<RetCode> {
+        if matches!(self.0.deadline, Some(deadline) if Instant::now() >= deadline) {
+            self.interrupt(CODE_TIMEOUT);
+        }
+        match self.0.code.load(Ordering::SeqCst) {
+            0 => None,
+            code => Some(code),
+        }
+    }
+}
+
+/// The body of the `env.{INTERRUPT_IMPORT}` import, which traps once the call
+/// bound to the instance is interrupted.
+pub(crate) fn check_interrupt(
+    handle: Option<&CancelHandle>,
+) -> std::result::Result<(), RuntimeError> {
+    match handle.and_then(CancelHandle::code) {
+        Some(code) => Err(RuntimeError::new(format!("call interrupted: code={}", code))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use wasmer::{ModuleMiddleware, Value};
+    use wasmer_types::ModuleInfo;
+
+    use super::Interrupt;
+    use crate::{
+        load_wasm, load_wasm_with, CancelHandle, Empty, LoadOptions, WasmCaller, CODE_CANCELED,
+        CODE_TIMEOUT,
+    };
+
+    const SPIN_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+        (memory (export "memory") 1)
+        (func $spin (loop br 0))
+        (func (export "_wasmy_wasm_handle_1") (param i32 i32) call $spin)
+        (func (export "_wasmy_wasm_handle_2") (param i32 i32)))"#;
+
+    const CALLS_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+        (import "wasi_snapshot_preview1" "sched_yield" (func $yield (result i32)))
+        (type $unary (func (param i32) (result i32)))
+        (memory (export "memory") 1)
+        (global $base (mut i32) (i32.const 0))
+        (table 2 funcref)
+        (elem (i32.const 0) $double $inc)
+        (func $start (global.set $base (i32.const 100)))
+        (start $start)
+        (func $double (param i32) (result i32) (i32.mul (local.get 0) (i32.const 2)))
+        (func $inc (param i32) (result i32) (i32.add (local.get 0) (i32.const 1)))
+        (func (export "compute") (param i32) (result i32)
+            (i32.add
+                (call $yield)
+                (call_indirect (type $unary)
+                    (call $double (i32.add (local.get 0) (global.get $base)))
+                    (i32.const 1)))))"#;
+
+    fn interruptible() -> LoadOptions {
+        LoadOptions { interruptible: true, ..Default::default() }
+    }
+
+    #[test]
+    fn interrupt() {
+        let wasm = wat::parse_str(SPIN_WAT).unwrap();
+        let caller = load_wasm_with(("interrupt_test", wasm), interruptible()).unwrap();
+        let err = caller
+            .call_with_timeout::<Empty, Empty>(1, Empty::new(), Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(err.code, CODE_TIMEOUT);
+        let handle = CancelHandle::new();
+        let canceler = handle.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceler.cancel();
+        });
+        let err = caller.call_with_cancel::<Empty, Empty>(1, Empty::new(), &handle).unwrap_err();
+        assert_eq!(err.code, CODE_CANCELED);
+        // the calls of the functions behind the import are not disturbed
+        let ret = caller.call_with_cancel::<Empty, Empty>(2, Empty::new(), &CancelHandle::new());
+        assert_ne!(ret.map_err(|e| e.code), Err(CODE_CANCELED));
+    }
+
+    #[test]
+    fn not_interruptible() {
+        let wasm = wat::parse_str(SPIN_WAT).unwrap();
+        let caller = load_wasm(("not_interruptible_test", wasm)).unwrap();
+        let err = caller
+            .call_with_cancel::<Empty, Empty>(2, Empty::new(), &CancelHandle::new())
+            .unwrap_err();
+        assert_eq!(err.code, CODE_CANCELED);
+    }
+
+    #[test]
+    fn shifted_calls() {
+        let compute = |caller: &WasmCaller| {
+            caller
+                .raw_call(
+                    "compute",
+                    |_| Ok(vec![Value::I32(1)].into_boxed_slice()),
+                    |_, rets| Ok(rets[0].unwrap_i32()),
+                )
+                .unwrap()
+        };
+        let wasm = wat::parse_str(CALLS_WAT).unwrap();
+        let plain = load_wasm(("shifted_calls_plain", wasm.clone())).unwrap();
+        let shifted = load_wasm_with(("shifted_calls_test", wasm), interruptible()).unwrap();
+        // (1 + 100) * 2 + 1, through the imports, the start function and the table
+        assert_eq!(compute(&plain), 203);
+        assert_eq!(compute(&shifted), 203);
+        let imports = shifted.describe().unwrap().imports;
+        assert_eq!(imports.len(), 2);
+    }
+
+    #[test]
+    fn layout() {
+        let interrupt = Interrupt::default();
+        assert!(interrupt.layout().is_err());
+        interrupt.transform_module_info(&mut ModuleInfo::new());
+        assert!(interrupt.layout().is_ok());
+        // the indices of one module are not reused for another
+        interrupt.transform_module_info(&mut ModuleInfo::new());
+        assert!(interrupt.layout().is_err());
+        // the imported functions must come first
+        let interrupt = Interrupt::default();
+        let mut module_info = ModuleInfo::new();
+        module_info.num_imported_functions = 1;
+        interrupt.transform_module_info(&mut module_info);
+        assert!(interrupt.layout().is_err());
+    }
+}
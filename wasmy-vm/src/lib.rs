@@ -1,19 +1,35 @@
-#![feature(unboxed_closures, fn_traits, thread_id_value)]
+#![feature(unboxed_closures)]
 
+pub use context::{HostContext, HostEnv};
+pub use describe::*;
 pub use entry::*;
 pub use handler::*;
 pub use instance::*;
+pub use interrupt::CancelHandle;
+pub use limits::ResourceLimits;
+pub use manifest::{Manifest, MethodTypes, RequiredMethod};
+pub use module::{MeteringConfig, ModuleCacheStats};
+pub use pool::{PoolConfig, PoolMetrics};
 pub use wasm_file::*;
-pub use wasmer::{import_namespace, Exports, Function, Imports, Module, Store};
+pub use wasmer::{import_namespace, Exports, ExternType, Function, Imports, Module, Store};
 pub use wasmer_wasi::{WasiFunctionEnv, WasiStateBuilder};
 pub use wasmy_abi::*;
+pub use watcher::*;
 
 mod context;
+mod describe;
 mod entry;
 mod handler;
 mod instance;
 mod instance_env;
+mod interrupt;
+mod limits;
+mod manifest;
+mod module;
+mod pool;
+mod stream;
 mod wasm_file;
+mod watcher;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,191 @@
+use std::{
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use wasmer::{
+    vm::{
+        MemoryError, MemoryStyle, TableStyle, VMMemory, VMMemoryDefinition, VMTable,
+        VMTableDefinition,
+    },
+    BaseTunables, Engine, MemoryType, Pages, Store, TableType, Target, Tunables,
+};
+use wasmer_vm::{LinearMemory, Trap};
+
+/// Resource limits of each instance of a wasm module, enforced when the
+/// memory and tables are created and grown.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceLimits {
+    /// The maximum pages (64 KiB each) of the linear memory, unlimited if none.
+    pub max_memory_pages: Option<u32>,
+    /// The maximum elements of each table, unlimited if none.
+    pub max_table_elements: Option<u32>,
+}
+
+impl ResourceLimits {
+    /// Create a store whose memories and tables are limited, with the record of
+    /// the failures to grow its memories.
+    pub(crate) fn new_store(&self, engine: impl Into<Engine>) -> (Store, GrowFailure) {
+        let base = BaseTunables::for_target(&Target::default());
+        let grow_failure = GrowFailure::default();
+        let tunables =
+            LimitingTunables { limits: self.clone(), base, grow_failure: grow_failure.clone() };
+        (Store::new_with_tunables(engine, tunables), grow_failure)
+    }
+}
+
+/// Whether a memory of the store has failed to grow, so a failed call is taken
+/// as out of memory.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GrowFailure(Arc<AtomicBool>);
+
+impl GrowFailure {
+    /// Whether a memory has failed to grow since the last time, clearing it.
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// The tunables capping the maximum of memories and tables, which fail to be
+/// created if the minimum exceeds the limit.
+struct LimitingTunables {
+    limits: ResourceLimits,
+    base: BaseTunables,
+    grow_failure: GrowFailure,
+}
+
+impl LimitingTunables {
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        if let Some(limit) = self.limits.max_memory_pages.map(Pages) {
+            adjusted.maximum = Some(adjusted.maximum.map_or(limit, |max| max.min(limit)));
+        }
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        match ty.maximum {
+            Some(max) if ty.minimum > max => Err(MemoryError::Generic(format!(
+                "the minimum {} pages of memory exceed the limit {} pages",
+                ty.minimum.0, max.0
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_grow_failure(&self, memory: VMMemory) -> VMMemory {
+        VMMemory(Box::new(RecordingMemory { memory, grow_failure: self.grow_failure.clone() }))
+    }
+
+    fn adjust_table(&self, requested: &TableType) -> TableType {
+        let mut adjusted = *requested;
+        if let Some(limit) = self.limits.max_table_elements {
+            adjusted.maximum = Some(adjusted.maximum.map_or(limit, |max| max.min(limit)));
+        }
+        adjusted
+    }
+
+    fn validate_table(&self, ty: &TableType) -> Result<(), String> {
+        match ty.maximum {
+            Some(max) if ty.minimum > max => Err(format!(
+                "the minimum {} elements of table exceed the limit {} elements",
+                ty.minimum, max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(&self.adjust_table(table))
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        let memory = self.base.create_host_memory(&adjusted, style)?;
+        Ok(self.record_grow_failure(memory))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        let memory = self.base.create_vm_memory(&adjusted, style, vm_definition_location)?;
+        Ok(self.record_grow_failure(memory))
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base.create_host_table(&adjusted, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base.create_vm_table(&adjusted, style, vm_definition_location)
+    }
+}
+
+/// The memory recording the failures to grow it.
+#[derive(Debug)]
+struct RecordingMemory {
+    memory: VMMemory,
+    grow_failure: GrowFailure,
+}
+
+impl LinearMemory for RecordingMemory {
+    fn ty(&self) -> MemoryType {
+        self.memory.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.memory.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.memory.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        let ret = self.memory.grow(delta);
+        if ret.is_err() {
+            self.grow_failure.0.store(true, Ordering::SeqCst);
+        }
+        ret
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.memory.vmmemory()
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn LinearMemory + 'static>> {
+        self.memory.try_clone()
+    }
+
+    unsafe fn initialize_with_data(&self, start: usize, data: &[u8]) -> Result<(), Trap> {
+        self.memory.initialize_with_data(start, data)
+    }
+}
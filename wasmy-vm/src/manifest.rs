@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use wasmer::Module;
+use wasmy_abi::*;
+
+use crate::handler::WasmHandlerApi;
+
+/// The custom section written by `wasm_requires!`, one record of the i32
+/// method, the u16 length of the label, and the label per vm method, in little
+/// endian.
+pub(crate) const REQUIRES_SECTION: &str = "wasmy.requires";
+
+/// The custom section written by `#[wasm_handle(Enum::VARIANT)]`, one record of
+/// the i32 method, the u16 length of the exported symbol, and the symbol per
+/// method, in little endian.
+pub(crate) const ENUMS_SECTION: &str = "wasmy.enums";
+
+/// The metadata of the wasm recorded by the macros: the message types of the
+/// wasm methods, the methods of the enum variants, and the vm methods that the
+/// wasm requires.
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    types: HashMap<String, MethodTypes>,
+    /// The methods of the symbols exported for the enum variants.
+    enums: HashMap<String, WasmMethod>,
+    /// The symbols exported for the enum variants, by the symbols of their
+    /// methods, e.g. `_wasmy_wasm_handle_2`.
+    enum_symbols: HashMap<String, String>,
+    requires: Vec<RequiredMethod>,
+}
+
+/// The full names of the protobuf messages of the args and the rets of a wasm
+/// method, e.g. `abi.Empty`, none if the macro could not tell.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MethodTypes {
+    pub args: Option<String>,
+    pub rets: Option<String>,
+}
+
+/// The vm method that the wasm requires, declared by `wasm_requires!`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequiredMethod {
+    pub method: VmMethod,
+    /// The name of a named or enum method, empty for a numeric one.
+    pub label: String,
+}
+
+impl Manifest {
+    /// Parse the manifest sections of the module, empty if it has none.
+    pub(crate) fn from_module(module: &Module) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for section in module.custom_sections(REQUIRES_SECTION) {
+            let records = parse_records(REQUIRES_SECTION, &section)?;
+            manifest.requires.extend(
+                records.into_iter().map(|(method, label)| RequiredMethod { method, label }),
+            );
+        }
+        for section in module.custom_sections(ENUMS_SECTION) {
+            for (method, symbol) in parse_records(ENUMS_SECTION, &section)? {
+                manifest.insert_enum(method, symbol);
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Record the message types returned by the `_wasmy_manifest` export.
+    pub(crate) fn insert_types_of(&mut self, data: &[u8]) -> Result<()> {
+        for (symbol, args, rets) in parse_types(data)? {
+            self.insert_types(symbol, args, rets);
+        }
+        Ok(())
+    }
+
+    /// Record the message types of the symbol, where an unknown type is empty.
+    fn insert_types(&mut self, symbol: String, args: String, rets: String) {
+        let type_name = |s: String| if s.is_empty() { None } else { Some(s) };
+        self.types.insert(symbol, MethodTypes { args: type_name(args), rets: type_name(rets) });
+    }
+
+    fn insert_enum(&mut self, method: WasmMethod, symbol: String) {
+        let method_symbol = WasmHandlerApi::method_to_symbol(method);
+        let method_symbol = if WasmHandlerApi::from_raw_symbol(&symbol).is_some() {
+            WasmHandlerApi::to_raw_symbol(&method_symbol)
+        } else if WasmHandlerApi::from_stream_symbol(&symbol).is_some() {
+            WasmHandlerApi::to_stream_symbol(&method_symbol)
+        } else {
+            method_symbol
+        };
+        self.enum_symbols.insert(method_symbol, symbol.clone());
+        self.enums.insert(symbol, method);
+    }
+
+    /// Get the method of the symbol exported by `#[wasm_handle(Enum::VARIANT)]`.
+    pub fn enum_method(&self, symbol: &str) -> Option<WasmMethod> {
+        self.enums.get(symbol).copied()
+    }
+
+    /// The symbol that the method symbol is exported by, which is another one
+    /// for the method of an enum variant.
+    pub(crate) fn export_symbol<'a>(&'a self, symbol: &'a str) -> &'a str {
+        self.enum_symbols.get(symbol).map_or(symbol, String::as_str)
+    }
+
+    /// Get the message types of the method exported by the symbol.
+    pub fn get(&self, symbol: &str) -> Option<&MethodTypes> {
+        self.types.get(symbol)
+    }
+
+    /// Get the vm methods that the wasm requires.
+    pub fn requires(&self) -> &[RequiredMethod] {
+        &self.requires
+    }
+
+    /// Check that the call uses the message types recorded for the symbol,
+    /// which passes if none is recorded.
+    pub(crate) fn check<A: Message, R: Message>(&self, symbol: &str) -> Result<()> {
+        let types = match self.get(self.export_symbol(symbol)) {
+            Some(types) => types,
+            None => return Ok(()),
+        };
+        let check = |kind: &str, expected: &Option<String>, actual: &str| match expected {
+            Some(expected) if expected != actual => CodeMsg::result(
+                CODE_PROTO,
+                format!(
+                    "the message type does not match: {} expects {} {}, but got {}",
+                    symbol, kind, expected, actual
+                ),
+            ),
+            _ => Ok(()),
+        };
+        check("args", &types.args, A::descriptor_static().full_name())?;
+        check("rets", &types.rets, R::descriptor_static().full_name())
+    }
+}
+
+/// Parse the records of the i32 method and the string of the u16 length.
+fn parse_records(section: &str, mut data: &[u8]) -> Result<Vec<(Method, String)>> {
+    let malformed = || CodeMsg::new(CODE_EXPORTS, format!("malformed {} section", section));
+    let mut records = vec![];
+    while !data.is_empty() {
+        let method = data.get(..4).ok_or_else(malformed)?;
+        let method = Method::from_le_bytes([method[0], method[1], method[2], method[3]]);
+        data = &data[4..];
+        records.push((method, take_str(&mut data).ok_or_else(malformed)?));
+    }
+    Ok(records)
+}
+
+/// Parse the records of the symbol, the args and the rets, each a string of the
+/// u16 length.
+fn parse_types(mut data: &[u8]) -> Result<Vec<(String, String, String)>> {
+    let malformed = || CodeMsg::new(CODE_EXPORTS, "malformed _wasmy_manifest");
+    let mut records = vec![];
+    while !data.is_empty() {
+        let mut take = || take_str(&mut data).ok_or_else(malformed);
+        records.push((take()?, take()?, take()?));
+    }
+    Ok(records)
+}
+
+/// Take the string of the u16 length from the front of the data.
+fn take_str(data: &mut &[u8]) -> Option<String> {
+    let len = data.get(..2)?;
+    let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+    let text = String::from_utf8(data.get(2..2 + len)?.to_vec()).ok()?;
+    *data = &data[2 + len..];
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_records, Manifest, MethodTypes, REQUIRES_SECTION};
+    use crate::{Empty, InArgs, CODE_PROTO};
+
+    #[test]
+    fn check() {
+        let mut manifest = Manifest::default();
+        let mut data = vec![20, 0];
+        data.extend_from_slice(b"_wasmy_wasm_handle_1");
+        data.extend_from_slice(&[9, 0]);
+        data.extend_from_slice(b"abi.Empty");
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&[20, 0]);
+        data.extend_from_slice(b"_wasmy_wasm_handle_3");
+        data.extend_from_slice(&[7, 0]);
+        data.extend_from_slice(b"b.Empty");
+        data.extend_from_slice(&[0, 0]);
+        assert!(manifest.insert_types_of(&data[..data.len() - 1]).is_err());
+        manifest.insert_types_of(&data).unwrap();
+        assert_eq!(
+            manifest.get("_wasmy_wasm_handle_1"),
+            Some(&MethodTypes { args: Some("abi.Empty".to_string()), rets: None })
+        );
+        assert!(manifest.check::<Empty, InArgs>("_wasmy_wasm_handle_1").is_ok());
+        assert!(manifest.check::<InArgs, InArgs>("_wasmy_wasm_handle_2").is_ok());
+        let err = manifest.check::<InArgs, Empty>("_wasmy_wasm_handle_1").unwrap_err();
+        assert_eq!(err.code, CODE_PROTO);
+        // a message of the same name in another package does not match
+        let err = manifest.check::<Empty, InArgs>("_wasmy_wasm_handle_3").unwrap_err();
+        assert_eq!(err.code, CODE_PROTO);
+    }
+
+    #[test]
+    fn requires() {
+        let mut data = 7i32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&(-3i32).to_le_bytes());
+        data.extend_from_slice(&[2, 0, b'k', b'v']);
+        let requires = parse_records(REQUIRES_SECTION, &data).unwrap();
+        assert_eq!(requires, vec![(7, String::new()), (-3, "kv".to_string())]);
+        assert!(parse_records(REQUIRES_SECTION, &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn enum_symbols() {
+        let mut manifest = Manifest::default();
+        manifest.insert_enum(2, "_wasmy_wasm_enum_Methods_3a_3aAdd".to_string());
+        manifest.insert_enum(2, "_wasmy_wasm_raw_enum_Methods_3a_3aAdd".to_string());
+        assert_eq!(manifest.enum_method("_wasmy_wasm_enum_Methods_3a_3aAdd"), Some(2));
+        assert_eq!(
+            manifest.export_symbol("_wasmy_wasm_handle_2"),
+            "_wasmy_wasm_enum_Methods_3a_3aAdd"
+        );
+        assert_eq!(
+            manifest.export_symbol("_wasmy_wasm_raw_handle_2"),
+            "_wasmy_wasm_raw_enum_Methods_3a_3aAdd"
+        );
+        assert_eq!(manifest.export_symbol("_wasmy_wasm_handle_3"), "_wasmy_wasm_handle_3");
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use wasmer::{wasmparser::Operator, CompilerConfig, Engine, Module, Store, Triple};
+use wasmer_cache::Hash;
+use wasmer_middlewares::Metering;
+use wasmy_abi::*;
+
+use crate::{interrupt::Interrupt, manifest::Manifest, LoadOptions, WasmUri};
+
+/// Instruction metering of the guest calls, compiled into the module.
+#[derive(Clone, Debug)]
+pub struct MeteringConfig {
+    /// The fuel of each call that is not given an explicit budget.
+    pub default_budget: u64,
+    /// The fuel cost of each operator.
+    pub cost_function: fn(&Operator) -> u64,
+    /// The identity of the cost function, which keys the on-disk artifact
+    /// cache and must change with the function. A function can not be told
+    /// apart by itself, so the artifacts of a cost function other than the
+    /// default are not cached without it.
+    pub cost_function_id: Option<String>,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        MeteringConfig {
+            default_budget: u32::MAX as u64,
+            cost_function: unit_cost,
+            cost_function_id: None,
+        }
+    }
+}
+
+/// The default cost function, one fuel per operator.
+fn unit_cost(_: &Operator) -> u64 {
+    1
+}
+
+/// Statistics of the compiled module cache of one wasm module.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleCacheStats {
+    /// Instantiations that reused the compiled module.
+    pub hits: u64,
+    /// Instantiations that had to compile the module.
+    pub misses: u64,
+    /// Compilations served by the on-disk precompiled artifact cache.
+    pub disk_hits: u64,
+    /// Time spent by the last compilation or artifact loading.
+    pub compile_time: Duration,
+}
+
+/// The compiled module of a wasm file, shared by all of its instances.
+#[derive(Clone)]
+pub(crate) struct CompiledModule {
+    pub(crate) engine: Engine,
+    pub(crate) module: Module,
+    pub(crate) manifest: Arc<Manifest>,
+    stats: Arc<CacheCounter>,
+}
+
+#[derive(Default)]
+struct CacheCounter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    disk_hits: AtomicU64,
+    compile_nanos: AtomicU64,
+}
+
+impl CompiledModule {
+    /// Count an instantiation that reuses the compiled module.
+    pub(crate) fn record_hit(&self) {
+        self.stats.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> ModuleCacheStats {
+        ModuleCacheStats {
+            hits: self.stats.hits.load(Ordering::Relaxed),
+            misses: self.stats.misses.load(Ordering::Relaxed),
+            disk_hits: self.stats.disk_hits.load(Ordering::Relaxed),
+            compile_time: Duration::from_nanos(self.stats.compile_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Compile the wasm bytes, or load the precompiled artifact of them.
+    pub(crate) fn compile(
+        wasm_uri: &WasmUri,
+        wasm_bytes: &[u8],
+        options: &LoadOptions,
+    ) -> Result<CompiledModule> {
+        #[cfg(debug_assertions)]
+        println!("compiling module, wasm_uri={}...", wasm_uri);
+
+        let mut compiler;
+        #[cfg(not(feature = "llvm"))]
+        {
+            compiler = wasmer_compiler_cranelift::Cranelift::default();
+            #[cfg(debug_assertions)]
+            println!("======== wasmy cranelift feature ========")
+        }
+        #[cfg(feature = "llvm")]
+        {
+            compiler = wasmer_compiler_llvm::LLVM::default();
+            #[cfg(debug_assertions)]
+            println!("======== wasmy llvm feature ========")
+        }
+
+        if options.interruptible {
+            compiler.push_middleware(Arc::new(Interrupt::default()));
+        }
+        if let Some(metering) = &options.metering {
+            compiler.push_middleware(Arc::new(Metering::new(
+                metering.default_budget,
+                metering.cost_function,
+            )));
+        }
+        let (store, _) = options.limits.new_store(compiler);
+        let stats = CacheCounter::default();
+        stats.misses.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let artifact_path =
+            options.artifact_cache.as_ref().and_then(|dir| artifact_path(dir, wasm_bytes, options));
+        let mut module = match artifact_path {
+            Some(path) => match load_artifact(&store, &path) {
+                Some(module) => {
+                    stats.disk_hits.fetch_add(1, Ordering::Relaxed);
+                    module
+                }
+                None => {
+                    let module = Module::from_binary(&store, wasm_bytes)?;
+                    store_artifact(&module, &path);
+                    module
+                }
+            },
+            None => Module::from_binary(&store, wasm_bytes)?,
+        };
+        let compile_time = start.elapsed();
+        module.set_name(wasm_uri.as_str());
+        let manifest = Manifest::from_module(&module)?;
+        #[cfg(debug_assertions)]
+        println!("compiled module, wasm_uri={}, elapsed={:?}", wasm_uri, compile_time);
+        stats.compile_nanos.store(compile_time.as_nanos() as u64, Ordering::Relaxed);
+        Ok(CompiledModule {
+            engine: store.engine().clone(),
+            module,
+            manifest: Arc::new(manifest),
+            stats: Arc::new(stats),
+        })
+    }
+}
+
+#[cfg(not(feature = "llvm"))]
+const COMPILER_NAME: &str = "cranelift";
+#[cfg(feature = "llvm")]
+const COMPILER_NAME: &str = "llvm";
+
+/// The identity of everything that affects the compiled code besides the wasm
+/// bytes, artifacts of other identities are never loaded. None if it can not be
+/// told, in which case the artifacts are not cached.
+fn engine_identity(options: &LoadOptions) -> Option<String> {
+    let mut identity = format!(
+        "wasmy-vm/{} wasmer/{} compiler/{} target/{}",
+        env!("CARGO_PKG_VERSION"),
+        wasmer::VERSION,
+        COMPILER_NAME,
+        Triple::host()
+    );
+    if options.interruptible {
+        identity.push_str(" interrupt/2");
+    }
+    if let Some(metering) = &options.metering {
+        let unit_cost: fn(&Operator) -> u64 = unit_cost;
+        let cost_function = match &metering.cost_function_id {
+            Some(id) => id.as_str(),
+            None if metering.cost_function as usize == unit_cost as usize => "unit",
+            None => return None,
+        };
+        identity.push_str(&format!(" metering/{}/{}", metering.default_budget, cost_function));
+    }
+    if let Some(pages) = options.limits.max_memory_pages {
+        identity.push_str(&format!(" memory/{}", pages));
+    }
+    if let Some(elements) = options.limits.max_table_elements {
+        identity.push_str(&format!(" table/{}", elements));
+    }
+    Some(identity)
+}
+
+fn artifact_path(dir: &Path, wasm_bytes: &[u8], options: &LoadOptions) -> Option<PathBuf> {
+    let mut key = engine_identity(options)?.into_bytes();
+    key.extend_from_slice(wasm_bytes);
+    Some(dir.join(format!("{}.wasmu", Hash::generate(&key).to_string())))
+}
+
+fn load_artifact(store: &Store, path: &Path) -> Option<Module> {
+    let bytes = fs::read(path).ok()?;
+    // SAFETY: the artifact is native code that is not validated, so a
+    // corrupted or forged one is undefined behavior, and the cache directory
+    // must be trusted. Only the header of the artifact is checked, which
+    // discards the ones of other wasmer versions, and an artifact of another
+    // engine identity is never read as its path differs.
+    match unsafe { Module::deserialize(store, bytes) } {
+        Ok(module) => {
+            #[cfg(debug_assertions)]
+            println!("loaded precompiled artifact: {:?}", path);
+            Some(module)
+        }
+        Err(_e) => {
+            #[cfg(debug_assertions)]
+            println!("discard precompiled artifact {:?}: {}", path, _e);
+            let _ = fs::remove_file(path);
+            None
+        }
+    }
+}
+
+/// Tell apart the temporary files of the threads of the process.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn store_artifact(module: &Module, path: &Path) {
+    let ret = module.serialize().map_err(|e| e.to_string()).and_then(|bytes| {
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        // write to a temporary file first, so that readers never see a partial
+        // artifact
+        let tmp = path.with_extension(format!(
+            "tmp{}.{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, &bytes).and_then(|_| fs::rename(&tmp, path)).map_err(|e| e.to_string())
+    });
+    // the artifact is only a cache, the module is compiled without it
+    if let Err(_e) = ret {
+        #[cfg(debug_assertions)]
+        println!("failed to store precompiled artifact {:?}: {}", path, _e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{engine_identity, MeteringConfig};
+    use crate::LoadOptions;
+
+    #[test]
+    fn cost_function_identity() {
+        let options = |metering| LoadOptions { metering: Some(metering), ..Default::default() };
+        assert!(engine_identity(&options(MeteringConfig::default())).is_some());
+        let custom = MeteringConfig { cost_function: |_| 2, ..Default::default() };
+        assert_eq!(engine_identity(&options(custom.clone())), None);
+        let identified = MeteringConfig { cost_function_id: Some("double".into()), ..custom };
+        assert_ne!(
+            engine_identity(&options(identified)),
+            engine_identity(&options(MeteringConfig::default()))
+        );
+    }
+
+    #[test]
+    fn interrupt_identity() {
+        let interruptible = LoadOptions { interruptible: true, ..Default::default() };
+        assert_ne!(engine_identity(&interruptible), engine_identity(&LoadOptions::default()));
+    }
+}
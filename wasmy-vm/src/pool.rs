@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use wasmy_abi::*;
+
+use crate::{
+    describe::ModuleDescription,
+    manifest::Manifest,
+    module::{CompiledModule, ModuleCacheStats},
+    wasm_file, Instance, LoadOptions, WasmUri,
+};
+
+lazy_static! {
+    static ref POOLS: RwLock<HashMap<WasmUri, Arc<InstancePool>>> = RwLock::new(HashMap::new());
+    /// Serializes the lazy loads of the registered files.
+    static ref LAZY_LOAD: Mutex<()> = Mutex::new(());
+}
+
+/// Sizing and eviction policy of the instance pool of one wasm module.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// The number of idle instances that are created when the module is
+    /// loaded, and never evicted.
+    pub min_size: usize,
+    /// The maximum number of live instances, checkout waits when it is reached.
+    pub max_size: usize,
+    /// Idle instances above `min_size` are dropped after this duration. The
+    /// eviction is lazy: there is no timer, the expired instances are dropped
+    /// when an instance is checked out or returned, or the config is set.
+    /// NOTE: The idle instances of a module that is no longer called are kept
+    /// until its next call, or until it is unloaded.
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_size: 1,
+            max_size: thread::available_parallelism().map_or(4, |n| n.get()),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Snapshot of the instance pool state of one wasm module.
+#[derive(Clone, Debug, Default)]
+pub struct PoolMetrics {
+    /// Instances waiting in the pool.
+    pub idle: usize,
+    /// Instances currently checked out by callers.
+    pub in_use: usize,
+    /// Total instances created.
+    pub created: u64,
+    /// Total idle instances dropped by eviction.
+    pub evicted: u64,
+    /// Total checkouts.
+    pub checkouts: u64,
+    /// Total checkouts that had to wait for an instance to be returned.
+    pub waits: u64,
+}
+
+pub(crate) struct InstancePool {
+    wasm_uri: WasmUri,
+    compiled: CompiledModule,
+    options: LoadOptions,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+struct PoolState {
+    config: PoolConfig,
+    idle: Vec<(Box<Instance>, Instant)>,
+    metrics: PoolMetrics,
+    next_id: usize,
+    closed: bool,
+}
+
+impl PoolState {
+    fn live(&self) -> usize {
+        self.metrics.idle + self.metrics.in_use
+    }
+    fn new(config: PoolConfig) -> Self {
+        PoolState {
+            config,
+            idle: vec![],
+            metrics: PoolMetrics::default(),
+            next_id: 0,
+            closed: false,
+        }
+    }
+    fn evict_expired(&mut self) {
+        let now = Instant::now();
+        while self.idle.len() > self.config.min_size {
+            // the oldest idle instance is at the front
+            if now.duration_since(self.idle[0].1) < self.config.idle_timeout {
+                break;
+            }
+            let (ins, _) = self.idle.remove(0);
+            #[cfg(debug_assertions)]
+            println!("evicted idle instance: wasm_uri={}, id={}", ins.wasm_uri(), ins.id());
+            drop(ins);
+            self.metrics.idle -= 1;
+            self.metrics.evicted += 1;
+        }
+    }
+}
+
+impl InstancePool {
+    fn new(wasm_uri: WasmUri, compiled: CompiledModule, options: LoadOptions) -> InstancePool {
+        InstancePool {
+            wasm_uri,
+            compiled,
+            state: Mutex::new(PoolState::new(options.pool.clone())),
+            options,
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Create the pool of the module with the first instance and the others
+    /// up to `min_size`, returning the replaced pool of the previous version.
+    pub(crate) fn install(
+        first: Box<Instance>,
+        compiled: CompiledModule,
+        options: LoadOptions,
+    ) -> Result<Option<Arc<InstancePool>>> {
+        let pool = Self::new(first.wasm_uri().clone(), compiled, options);
+        {
+            let mut state = pool.state.lock().unwrap();
+            let min_size = state.config.min_size.min(state.config.max_size.max(1));
+            let mut id = first.id();
+            state.idle.push((first, Instant::now()));
+            while state.idle.len() < min_size {
+                id += 1;
+                let ins = pool.create(id)?;
+                state.idle.push((ins, Instant::now()));
+            }
+            state.next_id = id + 1;
+            state.metrics.created = state.idle.len() as u64;
+            state.metrics.idle = state.idle.len();
+        }
+        Ok(POOLS.write().unwrap().insert(pool.wasm_uri.clone(), Arc::new(pool)))
+    }
+
+    /// Remove the pool of the module.
+    pub(crate) fn uninstall(wasm_uri: &WasmUri) -> Option<Arc<InstancePool>> {
+        POOLS.write().unwrap().remove(wasm_uri)
+    }
+
+    pub(crate) fn get(wasm_uri: &WasmUri) -> Option<Arc<InstancePool>> {
+        POOLS.read().unwrap().get(wasm_uri).cloned()
+    }
+
+    /// Get the pool of the loaded module. A module whose file is only
+    /// registered by `register_file` is loaded with the default options at
+    /// first, otherwise it fails with `CODE_WASI`.
+    pub(crate) fn get_loaded(wasm_uri: &WasmUri) -> Result<Arc<InstancePool>> {
+        if let Some(pool) = Self::get(wasm_uri) {
+            return Ok(pool);
+        }
+        let _guard = LAZY_LOAD.lock().unwrap();
+        if let Some(pool) = Self::get(wasm_uri) {
+            return Ok(pool);
+        }
+        let not_loaded =
+            || CodeMsg::new(CODE_WASI, format!("wasm is not loaded, wasm_uri={}", wasm_uri));
+        let bytes = wasm_file::get_files().get(wasm_uri).cloned().ok_or_else(not_loaded)?;
+        Instance::install((wasm_uri.as_str(), bytes), LoadOptions::default())?;
+        Self::get(wasm_uri).ok_or_else(not_loaded)
+    }
+
+    pub(crate) fn options(&self) -> &LoadOptions {
+        &self.options
+    }
+
+    pub(crate) fn manifest(&self) -> &Manifest {
+        &self.compiled.manifest
+    }
+
+    pub(crate) fn module_cache_stats(&self) -> ModuleCacheStats {
+        self.compiled.stats()
+    }
+
+    pub(crate) fn describe(&self) -> Result<ModuleDescription> {
+        let files = wasm_file::get_files();
+        ModuleDescription::new(
+            &self.wasm_uri,
+            &self.compiled.module,
+            &self.compiled.manifest,
+            files.get(&self.wasm_uri).map(Vec::as_slice),
+            &self.options.limits,
+        )
+    }
+
+    pub(crate) fn set_config(&self, config: PoolConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        state.evict_expired();
+        self.returned.notify_all();
+    }
+
+    pub(crate) fn metrics(&self) -> PoolMetrics {
+        self.state.lock().unwrap().metrics.clone()
+    }
+
+    /// Close the pool, wait for the in-flight calls to return their
+    /// instances, and drop all the instances.
+    pub(crate) fn drain(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.returned.notify_all();
+        while state.metrics.in_use > 0 {
+            state = self.returned.wait(state).unwrap();
+        }
+        state.idle.clear();
+        state.metrics.idle = 0;
+        #[cfg(debug_assertions)]
+        println!("drained instance pool: wasm_uri={}", self.wasm_uri);
+    }
+
+    /// Check out an idle instance, create a new one, or wait until one is
+    /// returned when the pool is full. Returns none if the pool is closed.
+    pub(crate) fn checkout(self: &Arc<Self>) -> Result<Option<CheckoutGuard>> {
+        let mut state = self.state.lock().unwrap();
+        let mut waited = false;
+        loop {
+            if state.closed {
+                return Ok(None);
+            }
+            state.evict_expired();
+            if let Some((ins, _)) = state.idle.pop() {
+                state.metrics.checkouts += 1;
+                state.metrics.idle -= 1;
+                state.metrics.in_use += 1;
+                return Ok(Some(CheckoutGuard { pool: self.clone(), ins: Some(ins) }));
+            }
+            if state.live() < state.config.max_size.max(1) {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.metrics.checkouts += 1;
+                state.metrics.in_use += 1;
+                drop(state);
+                let created = self.create(id);
+                let mut state = self.state.lock().unwrap();
+                match created {
+                    Ok(ins) => {
+                        state.metrics.created += 1;
+                        return Ok(Some(CheckoutGuard { pool: self.clone(), ins: Some(ins) }));
+                    }
+                    Err(e) => {
+                        state.metrics.in_use -= 1;
+                        self.returned.notify_all();
+                        return Err(e);
+                    }
+                }
+            }
+            if !waited {
+                waited = true;
+                state.metrics.waits += 1;
+            }
+            state = self.returned.wait(state).unwrap();
+        }
+    }
+
+    fn checkin(&self, ins: Box<Instance>) {
+        let mut state = self.state.lock().unwrap();
+        state.metrics.in_use -= 1;
+        if state.closed || ins.is_poisoned() {
+            drop(ins);
+        } else {
+            state.metrics.idle += 1;
+            state.idle.push((ins, Instant::now()));
+            state.evict_expired();
+        }
+        self.returned.notify_all();
+    }
+
+    /// Drop a checked out instance instead of returning it to the pool.
+    fn discard(&self, ins: Box<Instance>) {
+        drop(ins);
+        let mut state = self.state.lock().unwrap();
+        state.metrics.in_use -= 1;
+        self.returned.notify_all();
+    }
+
+    fn create(&self, id: usize) -> Result<Box<Instance>> {
+        self.compiled.record_hit();
+        Instance::create_local(self.wasm_uri.clone(), id, &self.compiled, &self.options, false)
+    }
+}
+
+/// An instance checked out from the pool, returned to it on drop.
+pub(crate) struct CheckoutGuard {
+    pool: Arc<InstancePool>,
+    ins: Option<Box<Instance>>,
+}
+
+impl Deref for CheckoutGuard {
+    type Target = Instance;
+
+    fn deref(&self) -> &Self::Target {
+        self.ins.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for CheckoutGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.ins.as_mut().unwrap()
+    }
+}
+
+impl Drop for CheckoutGuard {
+    fn drop(&mut self) {
+        if let Some(ins) = self.ins.take() {
+            // an instance unwound by a panic may be in an inconsistent state
+            if thread::panicking() {
+                self.pool.discard(ins)
+            } else {
+                self.pool.checkin(ins)
+            }
+        }
+    }
+}
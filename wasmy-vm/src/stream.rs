@@ -0,0 +1,153 @@
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+
+use wasmy_abi::*;
+
+use crate::context::Context;
+
+pub(crate) type StreamInput = Box<dyn Iterator<Item = Vec<u8>> + Send>;
+pub(crate) type StreamOutput = Box<dyn FnMut(&[u8]) -> Result<()> + Send>;
+
+/// The input and the output of the running stream call, taken from
+/// `WasmCaller::call_stream`. The wasm pulls the input chunks one at a time,
+/// and each output chunk is handed to the caller before the wasm goes on, so
+/// neither side runs ahead of the other.
+pub(crate) struct StreamState {
+    input: StreamInput,
+    output: StreamOutput,
+    /// The error of the output, which fails the call.
+    error: Option<CodeMsg>,
+}
+
+impl StreamState {
+    pub(crate) fn new(input: StreamInput, output: StreamOutput) -> Self {
+        StreamState { input, output, error: None }
+    }
+    /// Pull the next input chunk, skipping the empty ones, none at the end.
+    pub(crate) fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.input.next() {
+                Some(chunk) if chunk.is_empty() => continue,
+                chunk => return chunk,
+            }
+        }
+    }
+    /// Hand the output chunk to the caller, returning the error code if it
+    /// refuses the chunk, which also fails the call.
+    pub(crate) fn emit(&mut self, chunk: &[u8]) -> RetCode {
+        if let Some(err) = &self.error {
+            return err.code;
+        }
+        match (self.output)(chunk) {
+            Ok(()) => 0,
+            Err(err) => {
+                let code = if err.code == 0 { CODE_UNKNOWN } else { err.code };
+                self.error = Some(err);
+                code
+            }
+        }
+    }
+    pub(crate) fn take_error(&mut self) -> Option<CodeMsg> {
+        self.error.take()
+    }
+}
+
+impl Debug for StreamState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamState").field("error", &self.error).finish()
+    }
+}
+
+/// The state of the running stream call, shared by the call and the context.
+pub(crate) type StreamSlot = Arc<Mutex<StreamState>>;
+
+/// Expose the stream state to the stream imports through the context, for as
+/// long as the scope is alive. The scope takes the state out of the context
+/// when it is dropped, also by unwinding, so the state never outlives the call
+/// in the context.
+pub(crate) struct StreamScope<'a> {
+    ctx: &'a RefCell<Context>,
+}
+
+impl<'a> StreamScope<'a> {
+    pub(crate) fn enter(ctx: &'a RefCell<Context>, state: &StreamSlot) -> Self {
+        ctx.borrow_mut().stream = Some(state.clone());
+        StreamScope { ctx }
+    }
+
+    /// Run the callback with the state of the running stream call, none if no
+    /// stream call is running. The context is not borrowed meanwhile, as the
+    /// input and the output may take a while.
+    pub(crate) fn with<F, R>(ctx: &RefCell<Context>, callback: F) -> R
+    where
+        F: FnOnce(Option<&mut StreamState>) -> R,
+    {
+        let slot = ctx.borrow().stream.clone();
+        match slot {
+            Some(state) => callback(Some(&mut state.lock().unwrap())),
+            None => callback(None),
+        }
+    }
+}
+
+impl Drop for StreamScope<'_> {
+    fn drop(&mut self) {
+        self.ctx.borrow_mut().stream = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{StreamScope, StreamState};
+    use crate::{context::Context, CodeMsg, CODE_PROTO};
+
+    #[test]
+    fn stream_state() {
+        let input = vec![vec![1], vec![], vec![2]].into_iter();
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let output = {
+            let emitted = emitted.clone();
+            move |chunk: &[u8]| {
+                if chunk == [3] {
+                    return CodeMsg::result(CODE_PROTO, "refused");
+                }
+                emitted.lock().unwrap().extend_from_slice(chunk);
+                Ok(())
+            }
+        };
+        let mut stream = StreamState::new(Box::new(input), Box::new(output));
+        assert_eq!(stream.next(), Some(vec![1]));
+        assert_eq!(stream.next(), Some(vec![2]));
+        assert_eq!(stream.next(), None);
+        assert_eq!(stream.emit(&[1, 2]), 0);
+        assert_eq!(stream.emit(&[3]), CODE_PROTO);
+        assert_eq!(stream.emit(&[4]), CODE_PROTO);
+        assert_eq!(stream.take_error().unwrap().code, CODE_PROTO);
+        assert_eq!(*emitted.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn stream_scope() {
+        let ctx = RefCell::new(Context::with_capacity(0));
+        let input = vec![vec![1]].into_iter();
+        let output = |_: &[u8]| Ok(());
+        let state = Arc::new(Mutex::new(StreamState::new(Box::new(input), Box::new(output))));
+        {
+            let _scope = StreamScope::enter(&ctx, &state);
+            let chunk = StreamScope::with(&ctx, |state| state.and_then(|state| state.next()));
+            assert_eq!(chunk, Some(vec![1]));
+        }
+        // the state is out of the context once the scope is dropped
+        assert!(StreamScope::with(&ctx, |state| state.is_none()));
+        assert_eq!(Arc::strong_count(&state), 1);
+        assert_eq!(state.lock().unwrap().next(), None);
+    }
+}
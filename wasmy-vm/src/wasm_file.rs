@@ -65,12 +65,28 @@ lazy_static! {
 }
 
 pub fn register_file<F: WasmFile<B>, B: AsRef<[u8]>>(file: F) -> anyhow::Result<WasmUri> {
+    let (uri, bytes) = read_file(file)?;
+    GLOBAL_FILES.write().unwrap().insert(uri.clone(), bytes);
+    Ok(uri)
+}
+
+/// Read the file and convert the wat into wasm bytes, without registering it.
+pub(crate) fn read_file<F: WasmFile<B>, B: AsRef<[u8]>>(
+    file: F,
+) -> anyhow::Result<(WasmUri, Vec<u8>)> {
     let (uri, bytes) = file.into_parts()?;
     let bytes = wat::parse_bytes(bytes.as_ref()).map_err(|e| {
         CompileError::Wasm(WasmError::Generic(format!("Error when converting wat: {}", e)))
     })?;
-    GLOBAL_FILES.write().unwrap().insert(uri.clone(), bytes.to_vec());
-    Ok(uri)
+    Ok((uri, bytes.to_vec()))
+}
+
+pub(crate) fn insert_file(uri: WasmUri, bytes: Vec<u8>) {
+    GLOBAL_FILES.write().unwrap().insert(uri, bytes);
+}
+
+pub fn unregister_file(uri: &WasmUri) -> Option<Vec<u8>> {
+    GLOBAL_FILES.write().unwrap().remove(uri)
 }
 
 pub fn get_files() -> RwLockReadGuard<'static, HashMap<WasmUri, Vec<u8>>> {
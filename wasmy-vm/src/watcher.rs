@@ -0,0 +1,102 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use wasmy_abi::*;
+
+use crate::{Instance, LoadOptions, WasmCaller, WasmUri};
+
+/// Options of watching a wasm file for changes.
+#[derive(Clone, Debug)]
+pub struct WatchConfig {
+    /// Interval of polling the file metadata.
+    pub interval: Duration,
+    /// Called with the result of each reload triggered by a change.
+    pub on_reload: Option<fn(&WasmUri, &Result<()>)>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig { interval: Duration::from_secs(1), on_reload: None }
+    }
+}
+
+/// Watches a wasm file and hot-reloads it when it changes, until dropped.
+pub struct WasmWatcher {
+    wasm_uri: WasmUri,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WasmWatcher {
+    /// Get the URI of the watched wasm.
+    pub fn wasm_uri(&self) -> &WasmUri {
+        &self.wasm_uri
+    }
+}
+
+impl Drop for WasmWatcher {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Load the wasm file and reload it whenever the file changes on disk.
+/// Each reload compiles the file and runs `check_module` again; a failed reload
+/// is reported and the working version keeps serving calls.
+pub fn watch_wasm(
+    path: PathBuf,
+    options: LoadOptions,
+    config: WatchConfig,
+) -> Result<(WasmCaller, WasmWatcher)> {
+    let mut last = file_version(&path);
+    let wasm_uri = Instance::install(path.clone(), options)?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let handle = {
+        let stopped = stopped.clone();
+        let wasm_uri = wasm_uri.clone();
+        thread::Builder::new().name(format!("wasmy-watch:{}", wasm_uri)).spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                thread::park_timeout(config.interval);
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let current = file_version(&path);
+                if current.is_none() || current == last {
+                    continue;
+                }
+                last = current;
+                #[cfg(debug_assertions)]
+                println!("wasm file changed, reloading: wasm_uri={}", wasm_uri);
+                // a failure is reported to `on_reload`, the working version is kept
+                let ret = Instance::reinstall(path.clone()).map(|_| ());
+                #[cfg(debug_assertions)]
+                if let Err(e) = &ret {
+                    println!("failed to reload wasm, keep the working version: {}", e);
+                }
+                if let Some(on_reload) = config.on_reload {
+                    on_reload(&wasm_uri, &ret);
+                }
+            }
+        })?
+    };
+    Ok((wasm_uri.clone().into_caller(), WasmWatcher { wasm_uri, stopped, handle: Some(handle) }))
+}
+
+/// The modification time and size of the file, which change on every write.
+fn file_version(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}